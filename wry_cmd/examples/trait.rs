@@ -27,8 +27,6 @@ trait MyCommands {
 // Let's implement our trait
 struct MyApp;
 
-static INSTANCE: MyApp = MyApp;
-
 #[commands]
 impl MyCommands for MyApp {
     fn greet(&self, args: GreetArgs) -> GreetReply {
@@ -73,6 +71,9 @@ async function sendGreet() {
 
     "#;
 
+    // Register the instance the #[commands] methods are called on
+    wry_cmd::set_state(MyApp);
+
     // Build the Wry window + webview
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
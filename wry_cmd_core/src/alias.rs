@@ -0,0 +1,72 @@
+//! Alternate command names, with deprecation timelines for sunsetting them.
+
+use once_cell::sync::Lazy;
+use std::{cmp::Ordering, sync::RwLock};
+
+/// An alternate name a command also answers to,
+/// declared with `#[command(alias = "...")]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Alias {
+    pub name: &'static str,
+    /// Version the alias was deprecated in (`alias_deprecated_since = "..."`).
+    /// Calls through a deprecated alias log a warning naming the replacement.
+    pub deprecated_since: Option<&'static str>,
+    /// Version the alias goes away in (`alias_removed_in = "..."`).
+    /// Only enforced once the app calls [`gate_removed_aliases`].
+    pub removed_in: Option<&'static str>,
+}
+
+static CURRENT_VERSION: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+
+/// Reject calls through aliases whose `removed_in` version is at or below `current_version`.
+///
+/// Versions are compared component-wise on their dot-separated numbers
+/// (`"2.10"` is newer than `"2.9"`). Until this is called, removed aliases keep
+/// working and only log their deprecation warning.
+pub fn gate_removed_aliases(current_version: impl Into<String>) {
+    *CURRENT_VERSION.write().unwrap() = Some(current_version.into());
+}
+
+/// Check a call made through `alias` to `command`: error if the alias has been
+/// removed, otherwise warn if it's deprecated.
+pub(crate) fn check_alias(alias: &Alias, command: &str) -> Result<(), String> {
+    if let (Some(removed_in), Some(current)) =
+        (alias.removed_in, CURRENT_VERSION.read().unwrap().as_deref())
+    {
+        if compare_versions(current, removed_in) != Ordering::Less {
+            return Err(format!(
+                "`{}` was removed in {}; call `{}` instead",
+                alias.name, removed_in, command
+            ));
+        }
+    }
+    if let Some(since) = alias.deprecated_since {
+        log::warn!(
+            "Deprecated alias used: alias={} command={} deprecated_since={} removed_in={}",
+            alias.name,
+            command,
+            since,
+            alias.removed_in.unwrap_or("unscheduled"),
+        );
+    }
+    Ok(())
+}
+
+/// Compare dotted version strings numerically, treating missing or
+/// non-numeric components as `0`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    for i in 0..a.len().max(b.len()) {
+        let ord = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
@@ -0,0 +1,29 @@
+//! Per-deployment command allowlist, loaded once at startup.
+
+use once_cell::sync::Lazy;
+use std::{collections::HashSet, sync::RwLock};
+
+static ALLOWLIST: Lazy<RwLock<Option<HashSet<String>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Only allow the listed commands, e.g. from a license file or environment
+/// variable read at startup. Everything else fails with a "not licensed" error.
+///
+/// Entries are command names as registered (`"mycommands/greet"`); calls through
+/// an alias or with a path tail are checked against the command's own name.
+/// Calling this again replaces the list. Without an allowlist, every command is available.
+pub fn set_allowlist(commands: HashSet<String>) {
+    *ALLOWLIST.write().unwrap() = Some(commands);
+}
+
+/// Remove the allowlist, making every command available again.
+pub fn clear_allowlist() {
+    *ALLOWLIST.write().unwrap() = None;
+}
+
+/// Fail if an allowlist is set and doesn't include `cmd`.
+pub(crate) fn check_allowed(cmd: &str) -> Result<(), String> {
+    match &*ALLOWLIST.read().unwrap() {
+        Some(allowed) if !allowed.contains(cmd) => Err(format!("Command not licensed: {}", cmd)),
+        _ => Ok(()),
+    }
+}
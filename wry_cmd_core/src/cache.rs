@@ -0,0 +1,172 @@
+//! Server-side memoization for commands marked `#[command(cache_ttl_ms = ...)]`.
+//!
+//! Results are keyed by command name, path tail and serialized arguments, expire after
+//! the command's TTL, and are evicted least-recently-used once the cache is full.
+//! Only successful results are cached. Nothing else about the request is part of the
+//! key, so `#[command]` rejects caching on commands that read state, the request
+//! context or required headers.
+
+use crate::{CommandContext, CommandHandler, CommandResponse, DispatchError};
+#[cfg(not(feature = "sync-only"))]
+use futures::{future::BoxFuture, FutureExt};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Default maximum number of cached results across all commands.
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+struct Entry {
+    value: CommandResponse,
+    expires: Instant,
+    last_used: u64,
+}
+
+struct ResultCache {
+    entries: HashMap<(&'static str, String), Entry>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl ResultCache {
+    fn get(&mut self, key: &(&'static str, String)) -> Option<CommandResponse> {
+        let now = Instant::now();
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.expires > now => {
+                self.tick += 1;
+                entry.last_used = self.tick;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&mut self, key: (&'static str, String), value: CommandResponse, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            // Drop anything expired first, then the least recently used entry
+            self.entries.retain(|_, e| e.expires > now);
+            if self.entries.len() >= self.capacity {
+                if let Some(lru) = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_used)
+                    .map(|(k, _)| k.clone())
+                {
+                    self.entries.remove(&lru);
+                }
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                expires: now + ttl,
+                last_used: self.tick,
+            },
+        );
+    }
+}
+
+static CACHE: Lazy<Mutex<ResultCache>> = Lazy::new(|| {
+    Mutex::new(ResultCache {
+        entries: HashMap::new(),
+        capacity: DEFAULT_CACHE_CAPACITY,
+        tick: 0,
+    })
+});
+
+/// Set the maximum number of cached results (default [`DEFAULT_CACHE_CAPACITY`]).
+/// A capacity of `0` disables caching entirely.
+pub fn set_cache_capacity(capacity: usize) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.capacity = capacity;
+    while cache.entries.len() > capacity {
+        let lru = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(k, _)| k.clone())
+            .unwrap();
+        cache.entries.remove(&lru);
+    }
+}
+
+/// Drop every cached result.
+pub fn clear_cache() {
+    CACHE.lock().unwrap().entries.clear();
+}
+
+/// The cache key of one call: the command, its path tail and its arguments.
+fn cache_key(name: &'static str, ctx: &CommandContext, args: &Value) -> (&'static str, String) {
+    // The path tail is part of the request, so it has to be part of the key
+    match &ctx.path_tail {
+        Some(tail) => (name, format!("{}\n{}", tail, args)),
+        None => (name, args.to_string()),
+    }
+}
+
+/// Cache `result` under `key` if it's a success.
+fn store(
+    key: (&'static str, String),
+    result: &Result<CommandResponse, DispatchError>,
+    ttl_ms: u64,
+) {
+    if let Ok(value) = result {
+        CACHE
+            .lock()
+            .unwrap()
+            .put(key, value.clone(), Duration::from_millis(ttl_ms));
+    }
+}
+
+/// Serve `name(args)` from the cache, or run `handler` and cache a successful result.
+#[cfg(not(feature = "sync-only"))]
+pub(crate) fn cached(
+    name: &'static str,
+    ttl_ms: u64,
+    handler: CommandHandler,
+    ctx: CommandContext,
+    args: Value,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    let key = cache_key(name, &ctx, &args);
+    if let Some(hit) = CACHE.lock().unwrap().get(&key) {
+        return futures::future::ready(Ok(hit)).boxed();
+    }
+    async move {
+        let result = handler(ctx, args).await;
+        store(key, &result, ttl_ms);
+        result
+    }
+    .boxed()
+}
+
+/// Serve `name(args)` from the cache, or run `handler` and cache a successful result.
+#[cfg(feature = "sync-only")]
+pub(crate) fn cached(
+    name: &'static str,
+    ttl_ms: u64,
+    handler: CommandHandler,
+    ctx: CommandContext,
+    args: Value,
+) -> Result<CommandResponse, DispatchError> {
+    let key = cache_key(name, &ctx, &args);
+    if let Some(hit) = CACHE.lock().unwrap().get(&key) {
+        return Ok(hit);
+    }
+    let result = handler(ctx, args);
+    store(key, &result, ttl_ms);
+    result
+}
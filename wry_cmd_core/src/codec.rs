@@ -0,0 +1,65 @@
+//! Request and response body formats besides JSON, picked per request by `Content-Type`.
+//!
+//! Handlers still work on [`serde_json::Value`]: a codec turns the body into a
+//! `Value` before dispatch and the result back into its format afterwards. Formats
+//! with a native byte-string type therefore can't pass raw bytes to a handler as
+//! such; send binary fields as arrays of numbers, or return a
+//! [`CommandResponse::Bytes`](crate::CommandResponse::Bytes), which skips codecs.
+
+use serde_json::Value;
+
+/// A body format the protocol accepts and answers in, for requests sent with its
+/// [`content_type`](Codec::content_type). Add one with
+/// [`ProtocolOptions::codec`](crate::ProtocolOptions::codec).
+pub trait Codec: Send + Sync + 'static {
+    /// MIME type of this format, e.g. `"application/msgpack"`.
+    fn content_type(&self) -> &str;
+
+    /// Parse a request body into command arguments.
+    fn decode(&self, body: &[u8]) -> Result<Value, String>;
+
+    /// Serialize a response (the command's result in the configured envelope).
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String>;
+}
+
+/// MessagePack (`application/msgpack`), with struct fields as named map entries.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPack {
+    fn content_type(&self) -> &str {
+        "application/msgpack"
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        rmp_serde::from_slice(body).map_err(|e| e.to_string())
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        rmp_serde::to_vec_named(value).map_err(|e| e.to_string())
+    }
+}
+
+/// CBOR (`application/cbor`).
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn content_type(&self) -> &str {
+        "application/cbor"
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        ciborium::de::from_reader(body).map_err(|e| e.to_string())
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        ciborium::ser::into_writer(value, &mut out).map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+}
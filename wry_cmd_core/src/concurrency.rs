@@ -0,0 +1,128 @@
+//! Concurrency limits for commands marked `#[command(max_concurrent = ...)]`.
+//!
+//! Each limited command gets a small semaphore of its own, created on first use.
+//! Calls past the limit wait for a permit, or fail right away with
+//! `#[command(reject_when_busy)]`. The permit is held until the handler's future
+//! completes or is dropped (e.g. by a timeout).
+
+use crate::{CommandResponse, DispatchError};
+use futures::{future::BoxFuture, FutureExt};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Per-command semaphores, keyed by registered name.
+static LIMITERS: Lazy<Mutex<HashMap<&'static str, Arc<Limiter>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct Limiter {
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    available: usize,
+    waiters: Vec<Waker>,
+}
+
+impl Limiter {
+    fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        let mut state = self.state.lock().unwrap();
+        if state.available == 0 {
+            return None;
+        }
+        state.available -= 1;
+        Some(Permit(self.clone()))
+    }
+}
+
+/// A held slot, given back on drop.
+struct Permit(Arc<Limiter>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.0.state.lock().unwrap();
+            state.available += 1;
+            std::mem::take(&mut state.waiters)
+        };
+        // Every waiter retries; the ones that lose the race queue up again
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+/// Resolves once a permit is free.
+struct Acquire(Arc<Limiter>);
+
+impl Future for Acquire {
+    type Output = Permit;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.available > 0 {
+            state.available -= 1;
+            return Poll::Ready(Permit(self.0.clone()));
+        }
+        if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+fn limiter(name: &'static str, max: usize) -> Arc<Limiter> {
+    LIMITERS
+        .lock()
+        .unwrap()
+        .entry(name)
+        .or_insert_with(|| {
+            Arc::new(Limiter {
+                state: Mutex::new(LimiterState {
+                    available: max,
+                    waiters: Vec::new(),
+                }),
+            })
+        })
+        .clone()
+}
+
+/// Run the future `start` builds once one of `max` slots for `name` is free, or fail
+/// with `"too many concurrent requests"` when `reject` is set and none is.
+pub(crate) fn limited<F>(
+    name: &'static str,
+    max: usize,
+    reject: bool,
+    start: F,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>>
+where
+    F: FnOnce() -> BoxFuture<'static, Result<CommandResponse, DispatchError>> + Send + 'static,
+{
+    let limiter = limiter(name, max);
+    if reject {
+        return match limiter.try_acquire() {
+            Some(permit) => {
+                let fut = start();
+                async move {
+                    let _permit = permit;
+                    fut.await
+                }
+                .boxed()
+            }
+            None => futures::future::ready(Err(DispatchError::Handler(
+                "too many concurrent requests".to_string(),
+            )))
+            .boxed(),
+        };
+    }
+    async move {
+        let _permit = Acquire(limiter).await;
+        start().await
+    }
+    .boxed()
+}
@@ -0,0 +1,180 @@
+//! Per-request information handed to command handlers, and the argument
+//! parsing helpers generated handlers call with it.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Per-request information passed to every command handler.
+///
+/// The protocol builds one for each request; [`handle_command`](crate::handle_command)
+/// uses `CommandContext::default()`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandContext {
+    /// Reject argument objects containing fields the argument type doesn't declare.
+    pub deny_unknown_fields: bool,
+    /// The rest of the request path after the command name, for commands taking a
+    /// [`PathTail`]: `scheme://readfile/path/to/file.txt` → `path/to/file.txt`.
+    pub path_tail: Option<String>,
+    /// Request headers, keyed by lowercase name. Repeated headers are joined with `", "`.
+    /// Empty outside the protocol.
+    pub headers: HashMap<String, String>,
+    /// The full request URI, e.g. `mado://mycommands/greet`. Empty outside the protocol.
+    pub uri: String,
+    /// Id of the webview that sent the request, for multi-window apps routing replies
+    /// per window. Set by `use_wry_cmd_protocol!`; `None` when served without one.
+    pub webview_id: Option<String>,
+    /// Correlates this call with frontend logs: the request's `X-Request-Id`, or a
+    /// generated UUID. The protocol echoes it back in the response's `X-Request-Id`.
+    /// `None` outside the protocol.
+    pub request_id: Option<String>,
+}
+
+impl CommandContext {
+    /// A request header by (case-insensitive) name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// ` [request <id>]` for log lines about this call, or nothing without an id.
+    pub(crate) fn log_tag(&self) -> String {
+        self.request_id
+            .as_ref()
+            .map_or(String::new(), |id| format!(" [request {}]", id))
+    }
+}
+
+/// Handlers receive the request context directly: a command taking a
+/// `CommandContext` parameter (or marked `#[command(context)]`) gets a copy of it.
+impl FromContext for CommandContext {
+    fn from_context(ctx: &CommandContext) -> Result<Self, String> {
+        Ok(ctx.clone())
+    }
+}
+
+/// A command parameter filled from the request instead of the JSON arguments.
+///
+/// `#[command]` recognizes extractor parameters by type name and builds them with
+/// [`from_context`](FromContext::from_context); the remaining parameter, if any,
+/// still receives the deserialized JSON body.
+pub trait FromContext: Sized {
+    fn from_context(ctx: &CommandContext) -> Result<Self, String>;
+}
+
+/// Extractor for the remainder of the request path after the command name.
+///
+/// ```rust,ignore
+/// #[command]
+/// fn readfile(PathTail(path): PathTail) -> String {
+///     std::fs::read_to_string(path).unwrap_or_default()
+/// }
+/// ```
+///
+/// `fetch("mado://readfile/path/to/file.txt")` then calls it with `"path/to/file.txt"`.
+/// The tail is percent-decoded and empty when the command is called by its bare name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathTail(pub String);
+
+impl FromContext for PathTail {
+    fn from_context(ctx: &CommandContext) -> Result<Self, String> {
+        Ok(PathTail(ctx.path_tail.clone().unwrap_or_default()))
+    }
+}
+
+/// Deserialize a command's arguments, honoring the context's strictness.
+pub fn from_args<T: DeserializeOwned>(args: Value, ctx: &CommandContext) -> Result<T, String> {
+    if !ctx.deny_unknown_fields {
+        return serde_json::from_value(args).map_err(|e| e.to_string());
+    }
+
+    let mut unknown = Vec::new();
+    let value: T = serde_ignored::deserialize(args, |path| unknown.push(path.to_string()))
+        .map_err(|e| e.to_string())?;
+    if !unknown.is_empty() {
+        return Err(format!("unknown field(s): {}", unknown.join(", ")));
+    }
+    Ok(value)
+}
+
+/// Treat an empty object as `null`, so a lone `Option` argument is `None`
+/// whether the frontend sends no body, `null` or `{}`.
+#[doc(hidden)]
+pub fn empty_as_null(args: Value) -> Value {
+    match args {
+        Value::Object(map) if map.is_empty() => Value::Null,
+        args => args,
+    }
+}
+
+/// Check the arguments sent to a command that takes none.
+/// Anything is accepted unless the context is strict, in which case only
+/// `null` or an empty object is.
+pub fn check_no_args(args: &Value, ctx: &CommandContext) -> Result<(), String> {
+    if !ctx.deny_unknown_fields {
+        return Ok(());
+    }
+    match args {
+        Value::Null => Ok(()),
+        Value::Object(map) if map.is_empty() => Ok(()),
+        _ => Err("this command takes no arguments".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn lenient() -> CommandContext {
+        CommandContext::default()
+    }
+
+    fn strict() -> CommandContext {
+        CommandContext {
+            deny_unknown_fields: true,
+            ..CommandContext::default()
+        }
+    }
+
+    #[test]
+    fn lone_option_is_none_for_empty_null_or_omitted_body() {
+        // The protocol passes `null` for an omitted body
+        for ctx in [lenient(), strict()] {
+            for body in [json!({}), Value::Null] {
+                let arg: Option<u32> = from_args(empty_as_null(body.clone()), &ctx).unwrap();
+                assert_eq!(arg, None, "body {} (strict: {})", body, ctx.deny_unknown_fields);
+            }
+            let arg: Option<u32> = from_args(empty_as_null(json!(7)), &ctx).unwrap();
+            assert_eq!(arg, Some(7));
+        }
+    }
+
+    #[test]
+    fn empty_as_null_keeps_other_values() {
+        assert_eq!(empty_as_null(json!({ "a": 1 })), json!({ "a": 1 }));
+        assert_eq!(empty_as_null(json!([])), json!([]));
+        assert_eq!(empty_as_null(json!("")), json!(""));
+    }
+
+    #[test]
+    fn no_arg_command_accepts_empty_null_or_omitted_body() {
+        for ctx in [lenient(), strict()] {
+            for body in [json!({}), Value::Null] {
+                assert_eq!(check_no_args(&body, &ctx), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn no_arg_command_rejects_arguments_only_when_strict() {
+        for body in [json!({ "unexpected": 1 }), json!([1]), json!("x")] {
+            assert_eq!(check_no_args(&body, &lenient()), Ok(()));
+            assert_eq!(
+                check_no_args(&body, &strict()),
+                Err("this command takes no arguments".to_string())
+            );
+        }
+    }
+}
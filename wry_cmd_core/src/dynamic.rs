@@ -0,0 +1,237 @@
+//! Commands registered at runtime rather than through `#[command]`.
+
+use crate::{allowlist, hooks, normalize_command_name, CommandResponse, DispatchError};
+#[cfg(not(feature = "sync-only"))]
+use futures::{future::BoxFuture, Future, FutureExt};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+/// Handler for a command registered at runtime.
+#[cfg(not(feature = "sync-only"))]
+pub type DynamicHandler = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<Value, String>> + Send + Sync>;
+
+/// Handler for a command registered at runtime.
+#[cfg(feature = "sync-only")]
+pub type DynamicHandler = Arc<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+/// Handler for commands nobody registered, given the normalized command name.
+#[cfg(not(feature = "sync-only"))]
+pub type FallbackHandler =
+    Arc<dyn Fn(&str, Value) -> BoxFuture<'static, Result<Value, String>> + Send + Sync>;
+
+/// Handler for commands nobody registered, given the normalized command name.
+#[cfg(feature = "sync-only")]
+pub type FallbackHandler = Arc<dyn Fn(&str, Value) -> Result<Value, String> + Send + Sync>;
+
+static DYNAMIC_COMMANDS: Lazy<RwLock<HashMap<String, DynamicHandler>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+static FALLBACK: Lazy<RwLock<Option<FallbackHandler>>> = Lazy::new(|| RwLock::new(None));
+
+/// Wrap an async closure into a [`DynamicHandler`].
+#[cfg(not(feature = "sync-only"))]
+pub fn dynamic_handler<F, Fut>(f: F) -> DynamicHandler
+where
+    F: Fn(Value) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, String>> + Send + 'static,
+{
+    Arc::new(move |args| f(args).boxed())
+}
+
+/// Wrap a closure into a [`DynamicHandler`].
+#[cfg(feature = "sync-only")]
+pub fn dynamic_handler<F>(f: F) -> DynamicHandler
+where
+    F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+{
+    Arc::new(f)
+}
+
+/// Register (or replace) a command at runtime.
+/// Commands registered with `#[command]` take precedence over dynamic ones of the same name.
+pub fn register_dynamic(name: impl Into<String>, handler: DynamicHandler) {
+    DYNAMIC_COMMANDS
+        .write()
+        .unwrap()
+        .insert(name.into(), handler);
+}
+
+/// Remove a command registered at runtime. Returns `true` if it existed.
+pub fn unregister_dynamic(name: &str) -> bool {
+    DYNAMIC_COMMANDS.write().unwrap().remove(name).is_some()
+}
+
+/// Handle every command that matches neither a `#[command]` nor a runtime-registered
+/// one, e.g. to proxy it to a plugin system or a remote backend. Replaces any
+/// previous fallback; without one, unknown commands fail with
+/// [`DispatchError::UnknownCommand`].
+///
+/// ```rust,ignore
+/// wry_cmd::set_fallback(|name, args| {
+///     let name = name.to_string();
+///     async move { plugins::call(&name, args).await }.boxed()
+/// });
+/// ```
+#[cfg(not(feature = "sync-only"))]
+pub fn set_fallback<F>(fallback: F)
+where
+    F: Fn(&str, Value) -> BoxFuture<'static, Result<Value, String>> + Send + Sync + 'static,
+{
+    *FALLBACK.write().unwrap() = Some(Arc::new(fallback));
+}
+
+/// Handle every command that matches neither a `#[command]` nor a runtime-registered
+/// one. Replaces any previous fallback; without one, unknown commands fail with
+/// [`DispatchError::UnknownCommand`].
+#[cfg(feature = "sync-only")]
+pub fn set_fallback<F>(fallback: F)
+where
+    F: Fn(&str, Value) -> Result<Value, String> + Send + Sync + 'static,
+{
+    *FALLBACK.write().unwrap() = Some(Arc::new(fallback));
+}
+
+/// Remove the fallback set with [`set_fallback`].
+pub fn clear_fallback() {
+    *FALLBACK.write().unwrap() = None;
+}
+
+/// The fallback handler, if one is set.
+pub(crate) fn fallback() -> Option<FallbackHandler> {
+    FALLBACK.read().unwrap().clone()
+}
+
+/// Names of every runtime-registered command.
+pub(crate) fn dynamic_names() -> Vec<String> {
+    DYNAMIC_COMMANDS.read().unwrap().keys().cloned().collect()
+}
+
+/// Look up a runtime-registered command by its normalized name.
+pub(crate) fn find_dynamic(name: &str) -> Option<DynamicHandler> {
+    DYNAMIC_COMMANDS.read().unwrap().get(name).cloned()
+}
+
+/// An explicit set of commands, for setups where `inventory`'s link-time
+/// registration doesn't work (commands in a dylib, statics stripped by the linker).
+///
+/// Pass one to the protocol with `use_wry_cmd_protocol!("mado", registry = registry)`
+/// to serve only its commands; `#[command]` functions, upgrade commands and
+/// [`register_dynamic`] commands are then ignored by that protocol. Without one,
+/// the protocol serves the global, `inventory`-built registry. Either way, the hooks
+/// added with `add_before_hook`/`add_after_hook` and the allowlist apply.
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: HashMap<String, DynamicHandler>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry::default()
+    }
+
+    /// Register (or replace) a command.
+    #[cfg(not(feature = "sync-only"))]
+    pub fn register<F, Fut>(&mut self, name: impl Into<String>, handler: F) -> &mut Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, String>> + Send + 'static,
+    {
+        self.commands.insert(name.into(), dynamic_handler(handler));
+        self
+    }
+
+    /// Register (or replace) a command.
+    #[cfg(feature = "sync-only")]
+    pub fn register<F>(&mut self, name: impl Into<String>, handler: F) -> &mut Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.commands.insert(name.into(), dynamic_handler(handler));
+        self
+    }
+
+    /// Remove a command. Returns `true` if it existed.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    /// Names of the registered commands, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Dispatch a command by name (normalized like `handle_command`) with JSON arguments.
+    #[cfg(not(feature = "sync-only"))]
+    pub fn dispatch(
+        &self,
+        name: &str,
+        args: Value,
+    ) -> BoxFuture<'static, Result<Value, DispatchError>> {
+        let name = normalize_command_name(name);
+        match self.commands.get(&name) {
+            Some(handler) => handler(args).map(|r| r.map_err(DispatchError::Handler)).boxed(),
+            None => futures::future::ready(Err(DispatchError::UnknownCommand(name))).boxed(),
+        }
+    }
+
+    /// Dispatch a command by name (normalized like `handle_command`) with JSON arguments.
+    #[cfg(feature = "sync-only")]
+    pub fn dispatch(&self, name: &str, args: Value) -> Result<Value, DispatchError> {
+        let name = normalize_command_name(name);
+        match self.commands.get(&name) {
+            Some(handler) => handler(args).map_err(DispatchError::Handler),
+            None => Err(DispatchError::UnknownCommand(name)),
+        }
+    }
+
+    /// [`dispatch`](Self::dispatch) with the checks around every global command: the
+    /// before hooks and the allowlist first, then the after hooks. The protocol serves a
+    /// registry through this.
+    #[cfg(not(feature = "sync-only"))]
+    pub(crate) fn dispatch_checked(
+        &self,
+        raw_cmd: &str,
+        args: Value,
+    ) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+        let start = Instant::now();
+        let name = normalize_command_name(raw_cmd);
+        let fut = match admit_registry_command(&name, &args) {
+            Ok(()) => {
+                let fut = self.dispatch(&name, args).map(|r| r.map(CommandResponse::Json));
+                crate::startup::after_startup(fut.boxed())
+            }
+            Err(e) => futures::future::ready(Err(e)).boxed(),
+        };
+        hooks::run_after(name, start, fut)
+    }
+
+    /// [`dispatch`](Self::dispatch) with the checks around every global command: the
+    /// before hooks and the allowlist first, then the after hooks. The protocol serves a
+    /// registry through this.
+    #[cfg(feature = "sync-only")]
+    pub(crate) fn dispatch_checked(
+        &self,
+        raw_cmd: &str,
+        args: Value,
+    ) -> Result<CommandResponse, DispatchError> {
+        let start = Instant::now();
+        let name = normalize_command_name(raw_cmd);
+        let result = admit_registry_command(&name, &args)
+            .and_then(|()| self.dispatch(&name, args).map(CommandResponse::Json));
+        hooks::run_after_sync(&name, &result, start.elapsed());
+        result
+    }
+}
+
+/// The before hooks, then the allowlist, for a registry command.
+fn admit_registry_command(name: &str, args: &Value) -> Result<(), DispatchError> {
+    hooks::run_before(name, args)?;
+    allowlist::check_allowed(name).map_err(DispatchError::Unavailable)
+}
@@ -0,0 +1,40 @@
+//! Structured dispatch errors.
+
+use serde_json::Value;
+use std::fmt;
+
+/// Why a command couldn't produce a result.
+///
+/// `Display` gives the same message the string-based API returns, so
+/// `err.to_string()` is what [`handle_command`](crate::handle_command) reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    /// No command is registered under this (normalized) name.
+    UnknownCommand(String),
+    /// The arguments couldn't be deserialized into the command's argument type.
+    BadArgs(String),
+    /// The command exists but may not be called: it isn't on the allowlist,
+    /// or it was called through an alias that has been removed.
+    Unavailable(String),
+    /// The command ran and failed, or its result couldn't be serialized.
+    Handler(String),
+    /// The command failed with an error serialized as JSON, from
+    /// `#[command(error = "json")]`: the protocol sends it as the envelope's `error`
+    /// as-is, so frontends can read its fields.
+    Structured(Value),
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::UnknownCommand(cmd) => write!(f, "Unknown command: {}", cmd),
+            DispatchError::BadArgs(e)
+            | DispatchError::Unavailable(e)
+            | DispatchError::Handler(e) => f.write_str(e),
+            DispatchError::Structured(Value::String(e)) => f.write_str(e),
+            DispatchError::Structured(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
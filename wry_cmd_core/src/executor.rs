@@ -0,0 +1,27 @@
+//! Executors the protocol can run commands on instead of a thread per request.
+
+use futures::future::BoxFuture;
+
+/// Something that can run a command's future to completion in the background.
+///
+/// Implemented for `futures::executor::ThreadPool` and, with the `tokio` feature,
+/// `tokio::runtime::Handle`. Sync commands go through it too, unless
+/// [`inline_sync`](crate::ProtocolOptions::inline_sync) runs them on the thread serving
+/// the request. Blocking commands still tie up the executor's workers.
+pub trait Executor: Send + Sync + 'static {
+    fn spawn(&self, fut: BoxFuture<'static, ()>);
+}
+
+impl Executor for futures::executor::ThreadPool {
+    fn spawn(&self, fut: BoxFuture<'static, ()>) {
+        self.spawn_ok(fut);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Executor for tokio::runtime::Handle {
+    fn spawn(&self, fut: BoxFuture<'static, ()>) {
+        // the task is detached; it responds through its own responder
+        drop(tokio::runtime::Handle::spawn(self, fut));
+    }
+}
@@ -0,0 +1,92 @@
+//! Hooks run around every dispatch, for cross-cutting concerns like logging,
+//! latency measurement and authentication.
+
+use crate::{CommandResponse, DispatchError};
+#[cfg(not(feature = "sync-only"))]
+use futures::{future::BoxFuture, FutureExt};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+#[cfg(not(feature = "sync-only"))]
+use std::time::Instant;
+use std::{sync::RwLock, time::Duration};
+
+/// Guard run before a command: receives the normalized command name and its
+/// arguments, and rejects the call by returning `Err`.
+pub type BeforeHook = Box<dyn Fn(&str, &Value) -> Result<(), String> + Send + Sync>;
+
+/// Observer run once a command resolves: receives the normalized command name,
+/// its result and how long it took.
+pub type AfterHook =
+    Box<dyn Fn(&str, &Result<CommandResponse, DispatchError>, Duration) + Send + Sync>;
+
+static BEFORE_HOOKS: Lazy<RwLock<Vec<BeforeHook>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static AFTER_HOOKS: Lazy<RwLock<Vec<AfterHook>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Add a guard run before every dispatched command, in registration order.
+/// The first `Err` short-circuits: later guards and the command don't run, and
+/// the call fails with [`DispatchError::Unavailable`].
+///
+/// Hooks run on the dispatching thread and must not add hooks themselves.
+pub fn add_before_hook<F>(hook: F)
+where
+    F: Fn(&str, &Value) -> Result<(), String> + Send + Sync + 'static,
+{
+    BEFORE_HOOKS.write().unwrap().push(Box::new(hook));
+}
+
+/// Add an observer run after every dispatched command, in registration order,
+/// including those rejected by a before hook or unknown to the registry.
+pub fn add_after_hook<F>(hook: F)
+where
+    F: Fn(&str, &Result<CommandResponse, DispatchError>, Duration) + Send + Sync + 'static,
+{
+    AFTER_HOOKS.write().unwrap().push(Box::new(hook));
+}
+
+/// Add a metrics observer, e.g. feeding a Prometheus histogram: receives the
+/// normalized command name, whether the call succeeded and how long it took, timed
+/// from dispatch (before hooks and any [`on_startup`](crate::on_startup) wait
+/// included). Shorthand for an [`add_after_hook`] that only looks at `is_ok()`.
+pub fn add_metrics_hook<F>(hook: F)
+where
+    F: Fn(&str, bool, Duration) + Send + Sync + 'static,
+{
+    add_after_hook(move |name, result, elapsed| hook(name, result.is_ok(), elapsed));
+}
+
+/// Run the before hooks, stopping at the first rejection.
+pub(crate) fn run_before(name: &str, args: &Value) -> Result<(), DispatchError> {
+    for hook in BEFORE_HOOKS.read().unwrap().iter() {
+        hook(name, args).map_err(DispatchError::Unavailable)?;
+    }
+    Ok(())
+}
+
+/// Run the after hooks once `fut` resolves, timing from `start`.
+/// Adds nothing when none are registered.
+#[cfg(not(feature = "sync-only"))]
+pub(crate) fn run_after(
+    name: String,
+    start: Instant,
+    fut: BoxFuture<'static, Result<CommandResponse, DispatchError>>,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    if AFTER_HOOKS.read().unwrap().is_empty() {
+        return fut;
+    }
+    fut.map(move |result| {
+        run_after_sync(&name, &result, start.elapsed());
+        result
+    })
+    .boxed()
+}
+
+/// Run the after hooks for a command that already completed.
+pub(crate) fn run_after_sync(
+    name: &str,
+    result: &Result<CommandResponse, DispatchError>,
+    elapsed: Duration,
+) {
+    for hook in AFTER_HOOKS.read().unwrap().iter() {
+        hook(name, result, elapsed);
+    }
+}
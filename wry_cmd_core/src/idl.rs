@@ -0,0 +1,65 @@
+//! Register commands described by a JSON IDL, for providers that aren't Rust
+//! (WASM modules, subprocesses, ...).
+//!
+//! The IDL is a JSON document listing commands with optional JSON schemas:
+//!
+//! ```json
+//! {
+//!   "commands": [
+//!     { "name": "resize", "args": { "type": "object" }, "returns": { "type": "string" } }
+//!   ]
+//! }
+//! ```
+
+use crate::dynamic::{register_dynamic, DynamicHandler};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// A single command described in an IDL document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdlCommand {
+    pub name: String,
+    /// JSON schema of the arguments, if provided.
+    #[serde(default)]
+    pub args: Option<Value>,
+    /// JSON schema of the result, if provided.
+    #[serde(default)]
+    pub returns: Option<Value>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdlDocument {
+    commands: Vec<IdlCommand>,
+}
+
+/// Parse `idl` and register one dynamic command per entry, using `handler_factory`
+/// to build each command's handler. Returns the number of commands registered.
+///
+/// Nothing is registered if the document is malformed, a name is empty,
+/// or a name appears twice.
+pub fn register_from_idl<F>(idl: &str, mut handler_factory: F) -> Result<usize, String>
+where
+    F: FnMut(&IdlCommand) -> DynamicHandler,
+{
+    let doc: IdlDocument =
+        serde_json::from_str(idl).map_err(|e| format!("Invalid command IDL: {}", e))?;
+
+    let mut seen = HashSet::new();
+    for cmd in &doc.commands {
+        if cmd.name.trim_matches('/').is_empty() {
+            return Err("Invalid command IDL: empty command name".to_string());
+        }
+        if !seen.insert(cmd.name.as_str()) {
+            return Err(format!("Invalid command IDL: duplicate command `{}`", cmd.name));
+        }
+    }
+
+    for cmd in &doc.commands {
+        let handler = handler_factory(cmd);
+        register_dynamic(cmd.name.trim_matches('/'), handler);
+    }
+    Ok(doc.commands.len())
+}
@@ -0,0 +1,70 @@
+//! The built-in `$introspect` command, for discovering commands at runtime.
+
+use crate::{dynamic, Command, CommandContext, CommandResponse, DispatchError};
+#[cfg(not(feature = "sync-only"))]
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::{json, Value};
+
+/// Name of the built-in command listing every registered command, sorted by name, as
+/// `[{ "name": "greet", "args": "GreetArgs", "returns": "GreetReply" }, ...]`.
+///
+/// `args` and `returns` are the Rust types as written in source (`args` is `null` for
+/// commands without arguments); both are `null` for runtime-registered commands.
+pub const INTROSPECT_COMMAND: &str = "$introspect";
+
+#[cfg(not(feature = "sync-only"))]
+fn introspect(
+    ctx: CommandContext,
+    args: Value,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    futures::future::ready(introspect_sync(ctx, args)).boxed()
+}
+
+#[cfg(feature = "sync-only")]
+use introspect_sync as introspect;
+
+fn introspect_sync(
+    _ctx: CommandContext,
+    _args: Value,
+) -> Result<CommandResponse, DispatchError> {
+    let mut commands: Vec<(&str, Value)> = inventory::iter::<Command>
+        .into_iter()
+        .map(|c| {
+            let entry = json!({ "name": c.name, "args": c.args_type, "returns": c.returns_type });
+            (c.name, entry)
+        })
+        .collect();
+    let dynamic_names = dynamic::dynamic_names();
+    for name in &dynamic_names {
+        let entry = json!({ "name": name, "args": null, "returns": null });
+        commands.push((name.as_str(), entry));
+    }
+    commands.sort_by(|a, b| a.0.cmp(b.0));
+    let list: Vec<Value> = commands.into_iter().map(|(_, entry)| entry).collect();
+    Ok(CommandResponse::Json(Value::Array(list)))
+}
+
+inventory::submit! {
+    Command {
+        name: INTROSPECT_COMMAND,
+        handler: introspect,
+        sync_handler: Some(introspect_sync),
+        required_headers: &[],
+        cache_ttl_ms: None,
+        timeout: None,
+        max_concurrent: None,
+        reject_when_busy: false,
+        detach: false,
+        method: "GET",
+        accepts_path_tail: false,
+        aliases: &[],
+        deprecated: None,
+        smoke_test: true,
+        returns_warnings: false,
+        returns_unit: false,
+        error_status: None,
+        schema: None,
+        args_type: None,
+        returns_type: "Vec<Value>",
+    }
+}
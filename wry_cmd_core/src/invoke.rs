@@ -0,0 +1,59 @@
+//! Calling commands from Rust, e.g. in integration tests, without a webview.
+
+use crate::handle_command;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Call a command with typed arguments and result, going through the same dispatch
+/// path as the protocol (hooks, allowlist, aliases, startup hook, ...) minus HTTP.
+///
+/// ```rust,ignore
+/// let reply: GreetReply = wry_cmd::invoke("greet", GreetArgs { name: "Alice".into() }).await?;
+/// let sum: i32 = wry_cmd::invoke("add", (1, 2)).await?;
+/// ```
+///
+/// Pass `()` for commands without arguments. Errors are the messages the frontend
+/// would receive, plus argument and result (de)serialization failures.
+#[cfg(not(feature = "sync-only"))]
+pub async fn invoke<T, A>(name: &str, args: A) -> Result<T, String>
+where
+    T: DeserializeOwned,
+    A: Serialize,
+{
+    let value = handle_command(name, to_args(args)?).await?;
+    from_result(value)
+}
+
+/// [`invoke`], blocking the current thread until the command completes.
+/// Don't call it from inside an async runtime.
+#[cfg(not(feature = "sync-only"))]
+pub fn invoke_blocking<T, A>(name: &str, args: A) -> Result<T, String>
+where
+    T: DeserializeOwned,
+    A: Serialize,
+{
+    futures::executor::block_on(invoke(name, args))
+}
+
+/// Call a command with typed arguments and result, going through the same dispatch
+/// path as the protocol (hooks, allowlist, aliases, ...) minus HTTP.
+///
+/// Pass `()` for commands without arguments. Errors are the messages the frontend
+/// would receive, plus argument and result (de)serialization failures.
+#[cfg(feature = "sync-only")]
+pub fn invoke_blocking<T, A>(name: &str, args: A) -> Result<T, String>
+where
+    T: DeserializeOwned,
+    A: Serialize,
+{
+    let value = handle_command(name, to_args(args)?)?;
+    from_result(value)
+}
+
+fn to_args<A: Serialize>(args: A) -> Result<Value, String> {
+    serde_json::to_value(args).map_err(|e| format!("invalid args: {}", e))
+}
+
+fn from_result<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| format!("unexpected result: {}", e))
+}
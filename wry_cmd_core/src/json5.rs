@@ -0,0 +1,64 @@
+//! JSON5-style rendering of response bodies, for reading them in devtools.
+
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Render `value` as annotated JSON5: a leading comment naming the command,
+/// identifier-like keys unquoted, one entry per line and trailing commas.
+pub(crate) fn to_json5(cmd: &str, value: &Value) -> String {
+    let mut out = format!("// wry_cmd debug response for `{}`\n", cmd);
+    write_value(&mut out, value, 0);
+    out.push('\n');
+    out
+}
+
+fn write_value(out: &mut String, value: &Value, depth: usize) {
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for item in items {
+                indent(out, depth + 1);
+                write_value(out, item, depth + 1);
+                out.push_str(",\n");
+            }
+            indent(out, depth);
+            out.push(']');
+        }
+        Value::Object(map) if !map.is_empty() => {
+            out.push_str("{\n");
+            for (key, item) in map {
+                indent(out, depth + 1);
+                if is_identifier(key) {
+                    out.push_str(key);
+                } else {
+                    out.push_str(&Value::String(key.clone()).to_string());
+                }
+                out.push_str(": ");
+                write_value(out, item, depth + 1);
+                out.push_str(",\n");
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+        // scalars and empty containers are already valid JSON5
+        other => {
+            let _ = write!(out, "{}", other);
+        }
+    }
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+/// Whether `key` can be written without quotes (ASCII identifiers only).
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
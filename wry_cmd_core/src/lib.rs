@@ -4,150 +4,918 @@
 //!
 //! Core runtime for the Wry IPC command system.
 //! Provides the command registry and `handle_command` dispatcher,
-//! plus the `use_wry_ipc!()` macro for integrating with Wry.
+//! plus the `use_wry_cmd_protocol!()` macro for integrating with Wry.
 
 // Re-export inventory so macros in consumer crates can refer to it
 pub extern crate inventory;
 
+#[cfg(not(feature = "sync-only"))]
 pub use futures; // re-export futures for macro‐expansions
-use futures::{future::BoxFuture, FutureExt};
+pub use http; // re-export http for upgrade handler signatures
+#[cfg(feature = "introspection")]
+pub use schemars; // re-export schemars for per-command schemas
+
+#[cfg(all(feature = "async", feature = "sync-only"))]
+compile_error!(
+    "`async` and `sync-only` can't both be enabled: \
+     depend on wry_cmd with `default-features = false` to use `sync-only`"
+);
+#[cfg(not(any(feature = "async", feature = "sync-only")))]
+compile_error!("enable either the `async` (default) or the `sync-only` feature");
+
+mod alias;
+mod allowlist;
+mod cache;
+mod codec;
+#[cfg(not(feature = "sync-only"))]
+mod concurrency;
+mod context;
+mod dynamic;
+mod error;
+#[cfg(not(feature = "sync-only"))]
+mod executor;
+mod hooks;
+mod idl;
+mod introspect;
+mod invoke;
+mod json5;
+mod localize;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod protocol;
+mod response;
+mod smoke;
+#[cfg(not(feature = "sync-only"))]
+mod startup;
+mod state;
+#[cfg(not(feature = "sync-only"))]
+mod stream;
+#[cfg(not(feature = "sync-only"))]
+mod timeout;
+mod upgrade;
+
+pub use alias::{gate_removed_aliases, Alias};
+pub use allowlist::{clear_allowlist, set_allowlist};
+pub use cache::{clear_cache, set_cache_capacity, DEFAULT_CACHE_CAPACITY};
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+pub use codec::Codec;
+#[cfg(feature = "msgpack")]
+pub use codec::MsgPack;
+pub use context::{
+    check_no_args, empty_as_null, from_args, CommandContext, FromContext, PathTail,
+};
+pub use dynamic::{
+    clear_fallback, dynamic_handler, register_dynamic, set_fallback, unregister_dynamic,
+    CommandRegistry, DynamicHandler, FallbackHandler,
+};
+pub use error::DispatchError;
+#[cfg(not(feature = "sync-only"))]
+pub use executor::Executor;
+pub use hooks::{add_after_hook, add_before_hook, add_metrics_hook, AfterHook, BeforeHook};
+pub use idl::{register_from_idl, IdlCommand};
+pub use introspect::INTROSPECT_COMMAND;
+#[cfg(not(feature = "sync-only"))]
+pub use invoke::invoke;
+pub use invoke::invoke_blocking;
+#[cfg(feature = "metrics")]
+pub use metrics::{metrics_snapshot, reset_metrics, CommandMetrics, SizeStats};
+pub use localize::{set_error_localizer, ErrorLocalizer};
+pub use protocol::{serve, serve_webview, Envelope, ProtocolOptions};
+pub use response::{bigints_as_strings, CommandResponse};
+pub use smoke::run_smoke_tests;
+#[cfg(not(feature = "sync-only"))]
+pub use startup::on_startup;
+pub use state::{set_state, set_state_mutex, state, State};
+#[cfg(not(feature = "sync-only"))]
+pub use stream::{collect_stream, set_stream_limit, DEFAULT_STREAM_LIMIT};
+pub use upgrade::{Responder, UpgradeCommand, UpgradeHandler};
+
+#[cfg(not(feature = "sync-only"))]
+use futures::{future::BoxFuture, Future, FutureExt};
 use once_cell::sync::Lazy;
 use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
 /// Type alias for command handler functions.
-pub type CommandHandler = fn(Value) -> BoxFuture<'static, Result<Value, String>>;
+#[cfg(not(feature = "sync-only"))]
+pub type CommandHandler =
+    fn(CommandContext, Value) -> BoxFuture<'static, Result<CommandResponse, DispatchError>>;
+
+/// Type alias for command handler functions: with `sync-only`, every handler
+/// returns its result directly.
+#[cfg(feature = "sync-only")]
+pub type CommandHandler = fn(CommandContext, Value) -> Result<CommandResponse, DispatchError>;
+
+/// Type alias for the handlers of non-async commands, which run on the calling thread.
+pub type SyncCommandHandler =
+    fn(CommandContext, Value) -> Result<CommandResponse, DispatchError>;
+
+/// Futures a command handler can be boxed from: `Send` and `'static`.
+///
+/// Generated async handlers pass the command's future through
+/// [`assert_command_future`] so that a violation is reported on the command itself.
+#[cfg(not(feature = "sync-only"))]
+#[diagnostic::on_unimplemented(
+    message = "the future returned by this command cannot be used as a command handler",
+    label = "this command's future must be `Send + 'static`",
+    note = "handlers are boxed as `BoxFuture<'static, _>` and run on a protocol thread: \
+            don't hold non-`Send` values (e.g. `Rc`, `RefCell` borrows, `MutexGuard`) \
+            across an `.await`, and don't borrow from anything the command doesn't own"
+)]
+pub trait CommandFuture: Future + Send + 'static {}
+
+#[cfg(not(feature = "sync-only"))]
+impl<F: Future + Send + 'static> CommandFuture for F {}
+
+/// Identity function asserting that `fut` satisfies [`CommandFuture`].
+#[cfg(not(feature = "sync-only"))]
+#[doc(hidden)]
+pub fn assert_command_future<F: CommandFuture>(fut: F) -> F {
+    fut
+}
 
 /// A single registered command.
 pub struct Command {
     pub name: &'static str,
     pub handler: CommandHandler,
+    /// The same handler without a future, for non-async commands: called directly by
+    /// the protocol instead of boxing a future and moving it to a command thread.
+    pub sync_handler: Option<SyncCommandHandler>,
+    /// Headers that must be present on the request before the command is dispatched,
+    /// set with `#[command(require_header = "...")]`. Checked against
+    /// [`CommandContext::header`] on every dispatch, not only over the protocol.
+    pub required_headers: &'static [&'static str],
+    /// Memoize successful results for this many milliseconds,
+    /// set with `#[command(cache_ttl_ms = ...)]`.
+    pub cache_ttl_ms: Option<u64>,
+    /// Fail with `command timed out` if the handler takes longer than this,
+    /// set with `#[command(timeout_ms = ...)]`. Only async handlers can be interrupted.
+    pub timeout: Option<Duration>,
+    /// Run at most this many calls at once, set with `#[command(max_concurrent = ...)]`.
+    /// Further calls wait for a slot, or fail with `too many concurrent requests`
+    /// when [`reject_when_busy`](Self::reject_when_busy) is set.
+    pub max_concurrent: Option<usize>,
+    /// Fail calls past [`max_concurrent`](Self::max_concurrent) instead of queueing
+    /// them, set with `#[command(reject_when_busy)]`.
+    pub reject_when_busy: bool,
+    /// Fire-and-forget: the protocol answers `202 Accepted` with a job id
+    /// immediately and runs the handler in the background, set with `#[command(detach)]`.
+    pub detach: bool,
+    /// `"GET"` for side-effect-free commands that may also be called with GET
+    /// (args taken from the query string), `"POST"` otherwise,
+    /// set with `#[command(method = "...")]`.
+    pub method: &'static str,
+    /// Takes a [`PathTail`]: the command also answers `name/any/further/path`,
+    /// with the part after its name available to the handler.
+    pub accepts_path_tail: bool,
+    /// Other names the command answers to, set with `#[command(alias = "...")]`.
+    pub aliases: &'static [Alias],
+    /// Why the command is deprecated and what to call instead, set with
+    /// `#[command(deprecated = "...")]`. Every call logs a warning with it.
+    pub deprecated: Option<&'static str>,
+    /// Included in [`run_smoke_tests`]; cleared with `#[command(no_smoke_test)]`
+    /// for commands with side effects that shouldn't run in tests.
+    pub smoke_test: bool,
+    /// Returns [`WithWarnings`]: the protocol copies its warnings into `X-Warnings`.
+    pub returns_warnings: bool,
+    /// Returns `()` (or `Result<(), E>`): with the bare envelope, the protocol answers
+    /// a success with `204 No Content` and an empty body instead of `null`.
+    pub returns_unit: bool,
+    /// HTTP status the protocol sends when the command fails instead of
+    /// `500 Internal Server Error`, set with `#[command(error_status = ...)]`.
+    pub error_status: Option<u16>,
+    /// JSON schemas of the arguments and result, as `{ "args": ..., "returns": ... }`,
+    /// served at `scheme://_schema/<command>` with the `introspection` feature.
+    pub schema: Option<fn() -> Value>,
+    /// The argument type as written in source (a tuple for several arguments),
+    /// if the command takes any; reported by [`INTROSPECT_COMMAND`].
+    pub args_type: Option<&'static str>,
+    /// The type sent back on success as written in source (the `Ok` type of a `Result`).
+    pub returns_type: &'static str,
 }
 
 // Collect command registrations via `inventory`
 inventory::collect!(Command);
 
+/// Methods advertised for commands without more specific metadata.
+pub const DEFAULT_ALLOWED_METHODS: &str = "POST, OPTIONS";
+
+impl Command {
+    /// HTTP methods this command accepts, as advertised in CORS preflight
+    /// responses and `Allow` headers.
+    pub fn allowed_methods(&self) -> &'static str {
+        if self.accepts_get() {
+            "GET, POST, OPTIONS"
+        } else {
+            DEFAULT_ALLOWED_METHODS
+        }
+    }
+
+    /// Whether the command opted into GET with `#[command(method = "GET")]`.
+    pub fn accepts_get(&self) -> bool {
+        self.method == "GET"
+    }
+}
+
+/// A page of results with standard pagination metadata.
+///
+/// Return `Page<T>` from list commands so every paginated response
+/// has the same shape on the frontend:
+/// `{ "items": [...], "total": 42, "page": 1, "per_page": 20 }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "introspection", derive(schemars::JsonSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Total number of items across all pages.
+    pub total: u64,
+    /// Page index, as requested by the caller.
+    pub page: u32,
+    pub per_page: u32,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: u64, page: u32, per_page: u32) -> Self {
+        Page {
+            items,
+            total,
+            page,
+            per_page,
+        }
+    }
+}
+
+/// A successful result carrying non-fatal warnings
+/// ("saved, but 2 items were skipped").
+///
+/// Serialized as `{ "value": ..., "warnings": [...] }`. The protocol also lists
+/// the warnings in an `X-Warnings` header: comma-separated, each percent-encoded
+/// (decode with `decodeURIComponent`). The header is listed in
+/// `Access-Control-Expose-Headers`, so cross-origin pages can read it too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "introspection", derive(schemars::JsonSchema))]
+pub struct WithWarnings<T> {
+    pub value: T,
+    pub warnings: Vec<String>,
+}
+
+impl<T> WithWarnings<T> {
+    pub fn new(value: T, warnings: Vec<String>) -> Self {
+        WithWarnings { value, warnings }
+    }
+}
+
+/// Name → command index over everything registered through `inventory`.
+struct Registry {
+    commands: HashMap<&'static str, &'static Command>,
+    /// Alias name → the command it points at; real command names take precedence
+    aliases: HashMap<&'static str, (&'static Command, &'static Alias)>,
+    /// The same indexes keyed by ASCII-lowercased name, for case-insensitive lookup
+    folded_commands: HashMap<String, &'static Command>,
+    folded_aliases: HashMap<String, (&'static Command, &'static Alias)>,
+    build_time: Duration,
+}
+
+static CASE_INSENSITIVE: AtomicBool = AtomicBool::new(false);
+
+static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    let start = Instant::now();
+    let mut commands = HashMap::new();
+    for cmd_def in inventory::iter::<Command> {
+        // first registration wins, as with the previous linear lookup
+        commands.entry(cmd_def.name).or_insert(cmd_def);
+    }
+    let mut aliases = HashMap::new();
+    for cmd_def in inventory::iter::<Command> {
+        for alias in cmd_def.aliases {
+            if !commands.contains_key(alias.name) {
+                aliases.entry(alias.name).or_insert((cmd_def, alias));
+            }
+        }
+    }
+    let mut folded_commands = HashMap::new();
+    for (name, cmd_def) in &commands {
+        folded_commands
+            .entry(name.to_ascii_lowercase())
+            .or_insert(*cmd_def);
+    }
+    let mut folded_aliases = HashMap::new();
+    for (name, target) in &aliases {
+        folded_aliases
+            .entry(name.to_ascii_lowercase())
+            .or_insert(*target);
+    }
+    Registry {
+        commands,
+        aliases,
+        folded_commands,
+        folded_aliases,
+        build_time: start.elapsed(),
+    }
+});
+
+/// Build the command index now instead of on the first request,
+/// returning how long it took.
+///
+/// Useful to pre-warm at startup and to track startup cost as the number of
+/// commands grows. Later calls return the time of the original build.
+///
+/// # Panics
+/// In debug builds, if [`validate_registry`] finds colliding command names.
+pub fn init() -> Duration {
+    if cfg!(debug_assertions) {
+        if let Err(collisions) = validate_registry() {
+            panic!("duplicate command names:\n{}", collisions.join("\n"));
+        }
+    }
+    REGISTRY.build_time
+}
+
+/// Check the registry for names claimed more than once: two commands with the
+/// same name, an alias colliding with a command or another alias, or an upgrade
+/// command sharing a name with a regular one. Names differing only in separator
+/// (`files.read` and `files/read`) are reported too, since calls resolve with either;
+/// with [`set_case_insensitive`] on, so are names differing only in case.
+///
+/// Registration goes through `inventory`, so collisions compile fine and one of
+/// the commands silently wins at dispatch. Call this at startup to fail fast;
+/// the error lists every collision. [`init`] also checks this in debug builds.
+pub fn validate_registry() -> Result<(), Vec<String>> {
+    // name → what claimed it, in registration order
+    let mut claims: Vec<(&'static str, String)> = Vec::new();
+    for cmd_def in inventory::iter::<Command> {
+        claims.push((cmd_def.name, "command".to_string()));
+        for alias in cmd_def.aliases {
+            claims.push((alias.name, format!("alias of `{}`", cmd_def.name)));
+        }
+    }
+    for upgrade_def in inventory::iter::<UpgradeCommand> {
+        claims.push((upgrade_def.name, "upgrade command".to_string()));
+    }
+
+    let mut by_name: HashMap<&'static str, Vec<String>> = HashMap::new();
+    for (name, claim) in claims {
+        by_name.entry(name).or_default().push(claim);
+    }
+    let mut collisions: Vec<String> = Vec::new();
+    if CASE_INSENSITIVE.load(Ordering::Relaxed) {
+        let mut by_folded: HashMap<String, Vec<&'static str>> = HashMap::new();
+        for name in by_name.keys() {
+            by_folded.entry(name.to_ascii_lowercase()).or_default().push(name);
+        }
+        for (_, mut names) in by_folded {
+            if names.len() > 1 {
+                names.sort();
+                collisions.push(format!("{} differ only in case", names.join(" and ")));
+            }
+        }
+    }
+    // Calls resolve with either separator, so `a.b` and `a/b` are one name
+    let mut by_separator: HashMap<String, Vec<&'static str>> = HashMap::new();
+    for name in by_name.keys() {
+        by_separator.entry(name.replace('.', "/")).or_default().push(name);
+    }
+    for (_, mut names) in by_separator {
+        if names.len() > 1 {
+            names.sort();
+            collisions.push(format!("{} differ only in separator", names.join(" and ")));
+        }
+    }
+    collisions.extend(
+        by_name
+            .into_iter()
+            .filter(|(_, claims)| claims.len() > 1)
+            .map(|(name, claims)| {
+                format!(
+                    "`{}` is registered {} times: {}",
+                    name,
+                    claims.len(),
+                    claims.join(", ")
+                )
+            }),
+    );
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    collisions.sort();
+    Err(collisions)
+}
+
+/// Normalize a raw command name as received from the protocol.
+/// Strips leading/trailing slashes and percent-decodes the result,
+/// falling back to the undecoded name if decoding fails.
+pub fn normalize_command_name(raw_cmd: &str) -> String {
+    let cmd = raw_cmd.trim_matches('/');
+    percent_decode_str(cmd)
+        .decode_utf8()
+        .map(|cow| cow.to_string())
+        .unwrap_or_else(|_| cmd.to_string())
+}
+
+/// Look up a registered command by its raw (un-normalized) name.
+///
+/// Names with extra path segments resolve to a command taking a [`PathTail`].
+pub fn find_command(raw_cmd: &str) -> Option<&'static Command> {
+    resolve_command(&normalize_command_name(raw_cmd)).map(|resolved| resolved.cmd_def)
+}
+
+/// A normalized name resolved against the registry.
+struct Resolved {
+    cmd_def: &'static Command,
+    /// The rest of the path, for commands taking a [`PathTail`]
+    path_tail: Option<String>,
+    /// The alias the command was called through, if any
+    alias: Option<&'static Alias>,
+}
+
+/// Resolve a normalized name to a registered command.
+///
+/// An exact name wins, then an alias; otherwise the longest `/`-separated prefix
+/// naming a command that accepts a path tail is used, with the rest as the tail.
+/// If nothing matches, `.` and `/` are swapped and the lookup retried, so
+/// `service.method` reaches `service/method` and vice versa.
+fn resolve_command(cmd: &str) -> Option<Resolved> {
+    resolve_exact(cmd).or_else(|| {
+        // `service.method` and `service/method` name the same command
+        let swapped = if cmd.contains('.') {
+            cmd.replace('.', "/")
+        } else {
+            cmd.replace('/', ".")
+        };
+        (swapped != cmd).then(|| resolve_exact(&swapped)).flatten()
+    })
+}
+
+/// [`resolve_command`] for the name as given, without swapping separators.
+fn resolve_exact(cmd: &str) -> Option<Resolved> {
+    resolve_with(
+        cmd,
+        |name| REGISTRY.commands.get(name).copied(),
+        |name| REGISTRY.aliases.get(name).copied(),
+    )
+    .or_else(|| {
+        if !CASE_INSENSITIVE.load(Ordering::Relaxed) {
+            return None;
+        }
+        resolve_with(
+            cmd,
+            |name| REGISTRY.folded_commands.get(&name.to_ascii_lowercase()).copied(),
+            |name| REGISTRY.folded_aliases.get(&name.to_ascii_lowercase()).copied(),
+        )
+    })
+}
+
+/// [`resolve_command`] against one pair of command and alias lookups.
+fn resolve_with(
+    cmd: &str,
+    command: impl Fn(&str) -> Option<&'static Command>,
+    alias: impl Fn(&str) -> Option<(&'static Command, &'static Alias)>,
+) -> Option<Resolved> {
+    if let Some(cmd_def) = command(cmd) {
+        return Some(Resolved {
+            cmd_def,
+            path_tail: None,
+            alias: None,
+        });
+    }
+    if let Some((cmd_def, alias)) = alias(cmd) {
+        return Some(Resolved {
+            cmd_def,
+            path_tail: None,
+            alias: Some(alias),
+        });
+    }
+    cmd.rmatch_indices('/').find_map(|(i, _)| {
+        command(&cmd[..i])
+            .filter(|cmd_def| cmd_def.accepts_path_tail)
+            .map(|cmd_def| Resolved {
+                cmd_def,
+                path_tail: Some(cmd[i + 1..].to_string()),
+                alias: None,
+            })
+    })
+}
+
+/// Match command names ignoring ASCII case (`Greet` finds `greet`), after an
+/// exact match has failed. Off by default.
+///
+/// Free functions are registered lowercased but `#[commands]` methods keep their
+/// own casing, so this smooths over hand-typed URLs like `http://mado.Greet`.
+/// If two names differ only in case, one of them wins arbitrarily for the
+/// mismatched spelling; [`validate_registry`] reports such pairs while this is on,
+/// so enable it before validating.
+pub fn set_case_insensitive(enabled: bool) {
+    CASE_INSENSITIVE.store(enabled, Ordering::Relaxed);
+}
+
 /// Dispatch an IPC command by name with JSON arguments.
 /// Supports names like `"mycommands/greet"` or even `"/mycommands/greet"`
 /// and percent-encoded paths (e.g. `%2Fmycommands%2Fgreet`).
+/// If an [`on_startup`] hook is registered, the command waits for it to complete first.
+#[cfg(not(feature = "sync-only"))]
 pub fn handle_command(raw_cmd: &str, args: Value) -> BoxFuture<'static, Result<Value, String>> {
-    // 1) Normalize: strip leading/trailing slashes
-    let cmd = raw_cmd.trim_matches('/');
+    handle_command_with(raw_cmd, args, CommandContext::default())
+}
 
-    // 2) Percent-decode, falling back to the original if decoding fails
-    let cmd = percent_decode_str(cmd)
-        .decode_utf8()
-        .map(|cow| cow.to_string())
-        .unwrap_or_else(|_| cmd.to_string());
+/// Dispatch an IPC command by name with JSON arguments, on the calling thread.
+/// Supports names like `"mycommands/greet"` or even `"/mycommands/greet"`
+/// and percent-encoded paths (e.g. `%2Fmycommands%2Fgreet`).
+#[cfg(feature = "sync-only")]
+pub fn handle_command(raw_cmd: &str, args: Value) -> Result<Value, String> {
+    handle_command_with(raw_cmd, args, CommandContext::default())
+}
 
-    // 3) Lookup in the registry
-    for cmd_def in inventory::iter::<Command> {
-        if cmd_def.name == cmd {
-            return (cmd_def.handler)(args);
+/// Like [`handle_command`], with an explicit per-request [`CommandContext`].
+#[cfg(not(feature = "sync-only"))]
+pub fn handle_command_with(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> BoxFuture<'static, Result<Value, String>> {
+    handle_command_typed_with(raw_cmd, args, ctx)
+        .map(|result| result.map_err(|e| e.to_string()))
+        .boxed()
+}
+
+/// Like [`handle_command`], with an explicit per-request [`CommandContext`].
+#[cfg(feature = "sync-only")]
+pub fn handle_command_with(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> Result<Value, String> {
+    handle_command_typed_with(raw_cmd, args, ctx).map_err(|e| e.to_string())
+}
+
+/// Like [`handle_command`], but only for commands under `namespace`
+/// (see [`in_namespace`]): anything else fails as an unknown command.
+#[cfg(not(feature = "sync-only"))]
+pub fn handle_command_ns(
+    namespace: &str,
+    raw_cmd: &str,
+    args: Value,
+) -> BoxFuture<'static, Result<Value, String>> {
+    if !in_namespace(raw_cmd, namespace) {
+        let error = DispatchError::UnknownCommand(normalize_command_name(raw_cmd));
+        return futures::future::ready(Err(error.to_string())).boxed();
+    }
+    handle_command(raw_cmd, args)
+}
+
+/// Like [`handle_command`], but only for commands under `namespace`
+/// (see [`in_namespace`]): anything else fails as an unknown command.
+#[cfg(feature = "sync-only")]
+pub fn handle_command_ns(namespace: &str, raw_cmd: &str, args: Value) -> Result<Value, String> {
+    if !in_namespace(raw_cmd, namespace) {
+        let error = DispatchError::UnknownCommand(normalize_command_name(raw_cmd));
+        return Err(error.to_string());
+    }
+    handle_command(raw_cmd, args)
+}
+
+/// Whether `raw_cmd` is `namespace` itself or a name under it: `admin/users`
+/// (or `admin.users`) for the namespace `admin`.
+pub fn in_namespace(raw_cmd: &str, namespace: &str) -> bool {
+    let namespace = namespace.trim_matches('/');
+    match normalize_command_name(raw_cmd).strip_prefix(namespace) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Like [`handle_command`], reporting failures as a [`DispatchError`]
+/// so callers can tell unknown commands and bad arguments from handler errors.
+#[cfg(not(feature = "sync-only"))]
+pub fn handle_command_typed(
+    raw_cmd: &str,
+    args: Value,
+) -> BoxFuture<'static, Result<Value, DispatchError>> {
+    handle_command_typed_with(raw_cmd, args, CommandContext::default())
+}
+
+/// Like [`handle_command`], reporting failures as a [`DispatchError`]
+/// so callers can tell unknown commands and bad arguments from handler errors.
+#[cfg(feature = "sync-only")]
+pub fn handle_command_typed(raw_cmd: &str, args: Value) -> Result<Value, DispatchError> {
+    handle_command_typed_with(raw_cmd, args, CommandContext::default())
+}
+
+/// Like [`handle_command_typed`], with an explicit per-request [`CommandContext`].
+#[cfg(not(feature = "sync-only"))]
+pub fn handle_command_typed_with(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> BoxFuture<'static, Result<Value, DispatchError>> {
+    handle_command_response(raw_cmd, args, ctx)
+        .map(|result| result.map(CommandResponse::into_json))
+        .boxed()
+}
+
+/// Like [`handle_command_typed`], with an explicit per-request [`CommandContext`].
+#[cfg(feature = "sync-only")]
+pub fn handle_command_typed_with(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> Result<Value, DispatchError> {
+    handle_command_response(raw_cmd, args, ctx).map(CommandResponse::into_json)
+}
+
+/// Like [`handle_command_typed_with`], returning the full [`CommandResponse`]
+/// so binary results keep their content type. This is what the protocol uses.
+///
+/// Every variant runs the hooks added with [`add_before_hook`] and [`add_after_hook`].
+#[cfg(not(feature = "sync-only"))]
+pub fn handle_command_response(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    if let Some(route) = sync_route(raw_cmd) {
+        return futures::future::ready(run_sync(route, args, ctx)).boxed();
+    }
+    let start = Instant::now();
+    let name = normalize_command_name(raw_cmd);
+    let fut = match hooks::run_before(&name, &args) {
+        Ok(()) => startup::after_startup(dispatch(raw_cmd, args, ctx)),
+        Err(e) => futures::future::ready(Err(e)).boxed(),
+    };
+    hooks::run_after(name, start, fut)
+}
+
+/// Like [`handle_command_typed_with`], returning the full [`CommandResponse`]
+/// so binary results keep their content type. This is what the protocol uses.
+///
+/// Every variant runs the hooks added with [`add_before_hook`] and [`add_after_hook`].
+#[cfg(feature = "sync-only")]
+pub fn handle_command_response(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> Result<CommandResponse, DispatchError> {
+    if let Some(route) = sync_route(raw_cmd) {
+        return run_sync(route, args, ctx);
+    }
+    let start = Instant::now();
+    let name = normalize_command_name(raw_cmd);
+    let result = hooks::run_before(&name, &args).and_then(|()| dispatch(raw_cmd, args, ctx));
+    hooks::run_after_sync(&name, &result, start.elapsed());
+    result
+}
+
+/// A registered non-async command that can run on the calling thread.
+pub(crate) struct SyncRoute {
+    name: String,
+    resolved: Resolved,
+    handler: SyncCommandHandler,
+}
+
+/// Find the synchronous handler for `raw_cmd`, if it can skip the async path:
+/// not while an [`on_startup`] hook is pending, and not for cached or
+/// concurrency-limited commands.
+pub(crate) fn sync_route(raw_cmd: &str) -> Option<SyncRoute> {
+    #[cfg(not(feature = "sync-only"))]
+    if !startup::is_started() {
+        return None;
+    }
+    let name = normalize_command_name(raw_cmd);
+    let resolved = resolve_command(&name)?;
+    let handler = resolved.cmd_def.sync_handler?;
+    if resolved.cmd_def.cache_ttl_ms.is_some() || resolved.cmd_def.max_concurrent.is_some() {
+        return None;
+    }
+    Some(SyncRoute {
+        name,
+        resolved,
+        handler,
+    })
+}
+
+/// Run a command found by [`sync_route`] on the calling thread, with the same
+/// checks and hooks as [`handle_command_response`].
+pub(crate) fn run_sync(
+    route: SyncRoute,
+    args: Value,
+    ctx: CommandContext,
+) -> Result<CommandResponse, DispatchError> {
+    let start = Instant::now();
+    let result = hooks::run_before(&route.name, &args)
+        .and_then(|()| admit(route.resolved, ctx))
+        .and_then(|ctx| (route.handler)(ctx, args));
+    hooks::run_after_sync(&route.name, &result, start.elapsed());
+    result
+}
+
+/// Allowlist and alias checks for a resolved command; returns `ctx` with the
+/// command's path tail.
+fn admit(resolved: Resolved, mut ctx: CommandContext) -> Result<CommandContext, DispatchError> {
+    allowlist::check_allowed(resolved.cmd_def.name).map_err(DispatchError::Unavailable)?;
+    if let Some(alias) = resolved.alias {
+        alias::check_alias(alias, resolved.cmd_def.name).map_err(DispatchError::Unavailable)?;
+    }
+    let required = resolved.cmd_def.required_headers;
+    if let Some(missing) = required.iter().find(|h| ctx.header(h).is_none()) {
+        return Err(DispatchError::Unavailable(format!("Missing required header: {}", missing)));
+    }
+    if let Some(note) = resolved.cmd_def.deprecated {
+        log::warn!(
+            "Deprecated command called: {}: {}{}",
+            resolved.cmd_def.name,
+            note,
+            ctx.log_tag()
+        );
+    }
+    if resolved.path_tail.is_some() {
+        ctx.path_tail = resolved.path_tail;
+    }
+    Ok(ctx)
+}
+
+#[cfg(not(feature = "sync-only"))]
+fn dispatch(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    // 1) Normalize: strip slashes and percent-decode
+    let cmd = normalize_command_name(raw_cmd);
+
+    // 2) Lookup in the registry, splitting off any path tail
+    if let Some(resolved) = resolve_command(&cmd) {
+        let cmd_def = resolved.cmd_def;
+        let ctx = match admit(resolved, ctx) {
+            Ok(ctx) => ctx,
+            Err(e) => return futures::future::ready(Err(e)).boxed(),
+        };
+        let start = move || match cmd_def.cache_ttl_ms {
+            Some(ttl_ms) => cache::cached(cmd_def.name, ttl_ms, cmd_def.handler, ctx, args),
+            None => (cmd_def.handler)(ctx, args),
+        };
+        let fut = match cmd_def.max_concurrent {
+            Some(max) => concurrency::limited(cmd_def.name, max, cmd_def.reject_when_busy, start),
+            None => start(),
+        };
+        return match cmd_def.timeout {
+            Some(timeout) => timeout::with_timeout(fut, timeout),
+            None => fut,
+        };
+    }
+
+    // 3) Fall back to commands registered at runtime
+    if let Some(handler) = dynamic::find_dynamic(&cmd) {
+        if let Err(e) = allowlist::check_allowed(&cmd) {
+            return futures::future::ready(Err(DispatchError::Unavailable(e))).boxed();
         }
+        return handler(args)
+            .map(|result| {
+                result
+                    .map(CommandResponse::Json)
+                    .map_err(DispatchError::Handler)
+            })
+            .boxed();
     }
 
-    // 4) Unknown command
-    println!("Unknown command: {}", cmd);
-    println!(
-        "Available commands: {:?}",
-        inventory::iter::<Command>
-            .into_iter()
-            .map(|c| c.name)
-            .collect::<Vec<_>>()
-    );
-    futures::future::ready(Err(format!("Unknown command: {}", cmd))).boxed()
+    // 4) Hand anything else to the fallback, if one is set
+    if let Some(fallback) = dynamic::fallback() {
+        if let Err(e) = allowlist::check_allowed(&cmd) {
+            return futures::future::ready(Err(DispatchError::Unavailable(e))).boxed();
+        }
+        return fallback(&cmd, args)
+            .map(|result| {
+                result
+                    .map(CommandResponse::Json)
+                    .map_err(DispatchError::Handler)
+            })
+            .boxed();
+    }
+
+    // 5) Unknown command
+    futures::future::ready(Err(unknown_command(cmd, &ctx))).boxed()
+}
+
+/// [`dispatch`] on the calling thread, for `sync-only` builds.
+#[cfg(feature = "sync-only")]
+fn dispatch(
+    raw_cmd: &str,
+    args: Value,
+    ctx: CommandContext,
+) -> Result<CommandResponse, DispatchError> {
+    let cmd = normalize_command_name(raw_cmd);
+
+    if let Some(resolved) = resolve_command(&cmd) {
+        let cmd_def = resolved.cmd_def;
+        let ctx = admit(resolved, ctx)?;
+        return match cmd_def.cache_ttl_ms {
+            Some(ttl_ms) => cache::cached(cmd_def.name, ttl_ms, cmd_def.handler, ctx, args),
+            None => (cmd_def.handler)(ctx, args),
+        };
+    }
+
+    if let Some(handler) = dynamic::find_dynamic(&cmd) {
+        allowlist::check_allowed(&cmd).map_err(DispatchError::Unavailable)?;
+        return handler(args)
+            .map(CommandResponse::Json)
+            .map_err(DispatchError::Handler);
+    }
+
+    if let Some(fallback) = dynamic::fallback() {
+        allowlist::check_allowed(&cmd).map_err(DispatchError::Unavailable)?;
+        return fallback(&cmd, args)
+            .map(CommandResponse::Json)
+            .map_err(DispatchError::Handler);
+    }
+
+    Err(unknown_command(cmd, &ctx))
+}
+
+/// Log a command nobody handles, returning the error it fails with.
+fn unknown_command(cmd: String, ctx: &CommandContext) -> DispatchError {
+    log::warn!("Unknown command: {}{}", cmd, ctx.log_tag());
+    if log::log_enabled!(log::Level::Debug) {
+        log::debug!(
+            "Available commands: {:?}",
+            inventory::iter::<Command>
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>()
+        );
+    }
+    DispatchError::UnknownCommand(cmd)
 }
 
+/// Record the request and response body sizes of one dispatch of a registered command.
+/// Calls that didn't reach one (`name` is `None`) aren't recorded, so requests for
+/// arbitrary names can't grow the metrics. A no-op unless the `metrics` feature is enabled.
+fn record_payload_sizes(name: Option<&str>, request_bytes: usize, response_bytes: usize) {
+    #[cfg(feature = "metrics")]
+    if let Some(name) = name {
+        metrics::record_payload_sizes(name, request_bytes, response_bytes);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (name, request_bytes, response_bytes);
+}
+
+/// Build a custom-protocol handler for `WebViewBuilder::with_asynchronous_custom_protocol`.
+///
+/// Takes the scheme name, optionally followed by an [`Executor`] to run commands on
+/// and `key = value` options (see [`ProtocolOptions`]):
+///
+/// ```rust,ignore
+/// .with_asynchronous_custom_protocol("mado".into(), use_wry_cmd_protocol!("mado"))
+/// .with_asynchronous_custom_protocol(
+///     "mado".into(),
+///     use_wry_cmd_protocol!("mado", stack_size = 8 * 1024 * 1024),
+/// )
+/// .with_asynchronous_custom_protocol(
+///     "mado".into(),
+///     use_wry_cmd_protocol!("mado", runtime.handle().clone()),
+/// )
+/// ```
+///
+/// Arguments come from the JSON body, merged with the query string:
+/// `fetch("mado://search?q=foo")` calls `search` with `{ "q": "foo" }`.
+/// Query values are percent-decoded strings (a repeated key gives an array of them)
+/// and are merged only into an object body or a missing one: on a key present in
+/// both, the body wins, and a body that isn't an object is used as-is (in
+/// [strict](ProtocolOptions::strict) mode, the request is answered with
+/// `400 Bad Request` instead). An empty body is `null`; one that isn't valid JSON is
+/// answered with `400 Bad Request`.
+///
+/// Each request gets an id, from its `X-Request-Id` header or a generated UUID: it's
+/// echoed back in the response's `X-Request-Id` (exposed to cross-origin pages, and
+/// allowed in preflights), passed to handlers as
+/// [`CommandContext::request_id`] and included in the dispatch's log lines.
+///
+/// A command that panics is answered with `500` and the error `internal panic`
+/// (logging the panic message) instead of leaving the request unanswered.
 #[macro_export]
 macro_rules! use_wry_cmd_protocol {
-    ($scheme:expr) => {{
-        // Capture scheme name as String
-        let scheme = $scheme.to_string();
+    (@handler $options:ident) => {{
+        let options = $options;
 
-        move |_webview_id: wry::WebViewId<'_>,
+        move |webview_id: wry::WebViewId<'_>,
               request: wry::http::Request<Vec<u8>>,
               responder: wry::RequestAsyncResponder| {
-            use wry::http::{Method, Response, StatusCode};
-            use ::std::borrow::Cow;
-            use ::serde_json::Value;
-
-            // Handle CORS preflight
-            if request.method() == &Method::OPTIONS {
-                let resp = Response::builder()
-                    .status(StatusCode::NO_CONTENT)
-                    .header("Access-Control-Allow-Origin", "*")
-                    .header("Access-Control-Allow-Methods", "POST, OPTIONS")
-                    .header("Access-Control-Allow-Headers", "Content-Type")
-                    .body(Cow::Borrowed(&[][..]))
-                    .unwrap();
-                responder.respond(resp);
-                return;
-            }
-
-            // Only POST is allowed
-            if request.method() != &Method::POST {
-                let resp = Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .header("Allow", "POST, OPTIONS")
-                    .header("Access-Control-Allow-Origin", "*")
-                    .body(Cow::Borrowed(b"Method Not Allowed".as_ref()))
-                    .unwrap();
-                responder.respond(resp);
-                return;
-            }
-
-            // Extract command name from URI: "mado://greet" → "greet"
-            let uri = request.uri();
-            // 1. Extract host (authority) and path separately
-            let host = uri.authority().map(|a| a.as_str()).unwrap_or("");
-            let path = uri.path_and_query()
-                .map(|pq| pq.path())
-                .unwrap_or("");
-
-            // 2. Trim any leading slash on the path
-            let path = path.trim_start_matches('/');
-
-            // 3. Build the command name
-            let cmd = if host.is_empty() {
-                // no host, just path
-                path.to_string()
-            } else if path.is_empty() {
-                // host only
-                host.to_string()
-            } else {
-                // both host and path
-                format!("{}/{}", host, path)
-            };
-            // Parse JSON args from body
-            let args: Value = serde_json::from_slice(request.body()).unwrap_or_default();
-
-            // Spawn a background thread to handle both sync & async commands
-            std::thread::spawn(move || {
-                // `handle_command` is your registry entrypoint, now returning a Future<Value>
-                let fut = $crate::handle_command(&cmd, args);
-
-                // Wait for the command (sync commands should return an immediately-ready future)
-                let result_json = $crate::futures::executor::block_on(fut);
-
-                // Wrap any error into {"error": "..."}
-                let response_value = match result_json {
-                    Ok(v) => v,
-                    Err(e) => serde_json::json!({ "error": e }),
-                };
-
-                // Serialize response
-                let body = serde_json::to_vec(&response_value).unwrap_or_default();
-                let resp = Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", "application/json")
-                    .header("Access-Control-Allow-Origin", "*")
-                    .body(Cow::Owned(body))
-                    .unwrap();
-
-                // Send it back
-                responder.respond(resp);
+            // The id borrows from wry; `serve_webview` keeps an owned copy
+            $crate::serve_webview(&options, webview_id, request, move |resp| {
+                responder.respond(resp)
             });
         }
     }};
+    ($scheme:expr $(, $option:ident = $value:expr)* $(,)?) => {{
+        let options = $crate::ProtocolOptions::new($scheme)$(.$option($value))*;
+        $crate::use_wry_cmd_protocol!(@handler options)
+    }};
+    ($scheme:expr, $executor:expr $(, $option:ident = $value:expr)* $(,)?) => {{
+        let options = $crate::ProtocolOptions::new($scheme)
+            .executor($executor)
+            $(.$option($value))*;
+        $crate::use_wry_cmd_protocol!(@handler options)
+    }};
 }
@@ -0,0 +1,74 @@
+//! Localization of error messages, negotiated from `Accept-Language`.
+
+use crate::DispatchError;
+use http::{header::ACCEPT_LANGUAGE, HeaderMap};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::sync::RwLock;
+
+/// Translates an error for a language tag (`"de-CH"`, or `""` when the request
+/// didn't say), returning the envelope's `error` to send, or `None` to send the
+/// error unchanged.
+pub type ErrorLocalizer = fn(&str, &DispatchError) -> Option<Value>;
+
+static LOCALIZER: Lazy<RwLock<Option<ErrorLocalizer>>> = Lazy::new(|| RwLock::new(None));
+
+/// Transform every error the protocol sends, based on the request's
+/// `Accept-Language` header.
+///
+/// The localizer receives the caller's preferred language (the tag with the highest
+/// `q` value, `""` if there is no usable header) and the error, and returns the
+/// envelope's `error` to send: usually a translated string, or a translated copy of a
+/// [`DispatchError::Structured`] error. Matching on the variant tells the kinds of
+/// failure apart; requests rejected before dispatch arrive as
+/// [`BadArgs`](DispatchError::BadArgs) (malformed body) or
+/// [`Unavailable`](DispatchError::Unavailable) (missing header, not allowed).
+/// Without a localizer, or when it returns `None`, errors are sent as-is: their
+/// message, or a structured error's value.
+///
+/// ```rust,ignore
+/// wry_cmd::set_error_localizer(|lang, error| match (lang, error) {
+///     ("de", DispatchError::UnknownCommand(name)) => {
+///         Some(format!("Unbekannter Befehl: {}", name).into())
+///     }
+///     _ => None,
+/// });
+/// ```
+pub fn set_error_localizer(localizer: ErrorLocalizer) {
+    *LOCALIZER.write().unwrap() = Some(localizer);
+}
+
+/// The preferred language from an `Accept-Language` header, or `""`.
+pub(crate) fn preferred_language(headers: &HeaderMap) -> String {
+    let Some(header) = headers.get(ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()) else {
+        return String::new();
+    };
+    let mut best: Option<(&str, f32)> = None;
+    for entry in header.split(',') {
+        let mut parts = entry.split(';');
+        let tag = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if tag.is_empty() || tag == "*" || q <= 0.0 {
+            continue;
+        }
+        // the first of equally-weighted tags wins
+        if best.map_or(true, |(_, best_q)| q > best_q) {
+            best = Some((tag, q));
+        }
+    }
+    best.map(|(tag, _)| tag.to_string()).unwrap_or_default()
+}
+
+/// The envelope's `error` for `error`, run through the registered localizer, if any.
+pub(crate) fn localize(lang: &str, error: &DispatchError) -> Value {
+    let localizer = *LOCALIZER.read().unwrap();
+    localizer
+        .and_then(|localizer| localizer(lang, error))
+        .unwrap_or_else(|| match error {
+            DispatchError::Structured(error) => error.clone(),
+            error => Value::String(error.to_string()),
+        })
+}
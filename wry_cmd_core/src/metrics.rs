@@ -0,0 +1,65 @@
+//! Per-command diagnostics, enabled with the `metrics` feature.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
+
+/// Running min/max/average of a byte count.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SizeStats {
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+    pub count: u64,
+}
+
+impl SizeStats {
+    fn record(&mut self, bytes: u64) {
+        if self.count == 0 || bytes < self.min {
+            self.min = bytes;
+        }
+        self.max = self.max.max(bytes);
+        self.total += bytes;
+        self.count += 1;
+    }
+
+    /// Average size in bytes, or `0.0` if nothing was recorded.
+    pub fn avg(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+}
+
+/// Metrics recorded for a single command.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandMetrics {
+    /// Sizes of request bodies sent to the command.
+    pub request_bytes: SizeStats,
+    /// Sizes of serialized response bodies returned by the command.
+    pub response_bytes: SizeStats,
+}
+
+static METRICS: Lazy<Mutex<HashMap<String, CommandMetrics>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub(crate) fn record_payload_sizes(cmd: &str, request_bytes: usize, response_bytes: usize) {
+    let mut metrics = METRICS.lock().unwrap();
+    let entry = metrics.entry(cmd.to_string()).or_default();
+    entry.request_bytes.record(request_bytes as u64);
+    entry.response_bytes.record(response_bytes as u64);
+}
+
+/// A copy of the metrics recorded so far, keyed by registered command name: calls
+/// through an alias or the other separator count toward the command they reached,
+/// and unknown commands aren't recorded.
+pub fn metrics_snapshot() -> HashMap<String, CommandMetrics> {
+    METRICS.lock().unwrap().clone()
+}
+
+/// Discard all recorded metrics.
+pub fn reset_metrics() {
+    METRICS.lock().unwrap().clear();
+}
@@ -0,0 +1,1079 @@
+//! Request handling behind `use_wry_cmd_protocol!`.
+//!
+//! Responses are always fully buffered: wry's `RequestAsyncResponder::respond`
+//! takes the complete body in a single call, so there is no way to write a
+//! response in chunks (or to keep a connection open) from a custom protocol.
+//! [`CommandResponse::File`] results keep memory down by reading only what is sent:
+//! media elements fetch large files a `Range` at a time, and each range is answered
+//! with `206 Partial Content` from just those bytes of the file.
+
+#[cfg(not(feature = "sync-only"))]
+use crate::Executor;
+use crate::{
+    allowlist, find_command, Codec, handle_command_response, handle_command_with, localize,
+    normalize_command_name, upgrade, CommandContext, CommandRegistry, CommandResponse,
+    DispatchError, DEFAULT_ALLOWED_METHODS,
+};
+#[cfg(not(feature = "sync-only"))]
+use futures::{Future, FutureExt};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde_json::{Map, Value};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Source of job ids handed out for detached commands.
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Shape of the JSON body sent back for command results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Envelope {
+    /// The bare result on success, `{ "error": "..." }` on failure (`"bare"`).
+    #[default]
+    Bare,
+    /// `{ "success": true, "data": ... }` on success,
+    /// `{ "success": false, "error": "..." }` on failure (`"success-bool"`).
+    SuccessBool,
+}
+
+impl Envelope {
+    /// Wrap a command result into the response body.
+    pub fn wrap(self, result: Result<Value, String>) -> Value {
+        self.wrap_value(result.map_err(Value::String))
+    }
+
+    /// Like [`wrap`](Self::wrap), for an error that is any JSON value
+    /// (see [`DispatchError::Structured`]).
+    pub fn wrap_value(self, result: Result<Value, Value>) -> Value {
+        match (self, result) {
+            (Envelope::Bare, Ok(v)) => v,
+            (Envelope::Bare, Err(e)) => serde_json::json!({ "error": e }),
+            (Envelope::SuccessBool, Ok(v)) => serde_json::json!({ "success": true, "data": v }),
+            (Envelope::SuccessBool, Err(e)) => {
+                serde_json::json!({ "success": false, "error": e })
+            }
+        }
+    }
+
+    /// Wrap an already-serialized successful result into the response body.
+    pub fn wrap_raw(self, raw: Vec<u8>) -> Vec<u8> {
+        match self {
+            Envelope::Bare => raw,
+            Envelope::SuccessBool => {
+                let mut body = br#"{"success":true,"data":"#.to_vec();
+                body.extend_from_slice(&raw);
+                body.push(b'}');
+                body
+            }
+        }
+    }
+}
+
+impl FromStr for Envelope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bare" => Ok(Envelope::Bare),
+            "success-bool" => Ok(Envelope::SuccessBool),
+            other => Err(format!(
+                "unknown envelope preset `{}` (expected \"bare\" or \"success-bool\")",
+                other
+            )),
+        }
+    }
+}
+
+/// Options for `use_wry_cmd_protocol!`, given as `key = value` pairs after the scheme:
+///
+/// ```rust,ignore
+/// use_wry_cmd_protocol!("mado", stack_size = 8 * 1024 * 1024)
+/// ```
+///
+/// Each key maps to the builder method of the same name.
+#[derive(Clone)]
+pub struct ProtocolOptions {
+    scheme: String,
+    #[cfg(not(feature = "sync-only"))]
+    executor: Option<Arc<dyn Executor>>,
+    registry: Option<Arc<CommandRegistry>>,
+    stack_size: Option<usize>,
+    inline_sync: bool,
+    envelope: Envelope,
+    strict: bool,
+    debug_json5: bool,
+    cors: HeaderValue,
+    codecs: Vec<Arc<dyn Codec>>,
+    strip_prefix: Option<String>,
+    namespace: Option<String>,
+}
+
+impl ProtocolOptions {
+    pub fn new(scheme: impl Into<String>) -> Self {
+        ProtocolOptions {
+            scheme: scheme.into(),
+            #[cfg(not(feature = "sync-only"))]
+            executor: None,
+            registry: None,
+            stack_size: None,
+            inline_sync: false,
+            envelope: Envelope::Bare,
+            strict: false,
+            debug_json5: false,
+            cors: HeaderValue::from_static("*"),
+            codecs: default_codecs(),
+            strip_prefix: None,
+            namespace: None,
+        }
+    }
+
+    /// The custom protocol scheme these options were built for.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Run commands on `executor` (a tokio `Handle`, a futures `ThreadPool`, ...)
+    /// instead of spawning a thread per request, bounding thread churn under load.
+    /// Upgrade commands still get a thread of their own.
+    #[cfg(not(feature = "sync-only"))]
+    pub fn executor(mut self, executor: impl Executor) -> Self {
+        self.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Serve only the commands in `registry` instead of the global one;
+    /// see [`CommandRegistry`]. Before/after hooks and the allowlist still apply.
+    pub fn registry(mut self, registry: CommandRegistry) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// Stack size in bytes for the threads commands run on.
+    /// Defaults to the platform's default thread stack size.
+    /// Has no effect on commands run on an [`executor`](Self::executor).
+    /// Turns off [`inline_sync`](Self::inline_sync), whose thread's stack size
+    /// can't be changed.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Run sync commands right on the thread serving the request, skipping the
+    /// command thread (or executor) and its handoff. Off by default: that thread is
+    /// wry's event loop, so the UI freezes for as long as the command runs. Only
+    /// enable it when every sync command is quick; slow ones should be `async`.
+    ///
+    /// Cached, concurrency-limited and registry commands always take the usual
+    /// path, as does everything while [`stack_size`](Self::stack_size) is set.
+    pub fn inline_sync(mut self, enabled: bool) -> Self {
+        self.inline_sync = enabled;
+        self
+    }
+
+    /// Response envelope preset, by name: `"bare"` (default) or `"success-bool"`.
+    ///
+    /// With `"bare"`, commands returning `()` answer a success with `204 No Content`
+    /// and no body. `"success-bool"` always sends a body, so they answer
+    /// `200 OK` with `{ "success": true, "data": null }` instead.
+    ///
+    /// # Panics
+    /// If the preset name is unknown.
+    pub fn envelope(mut self, preset: &str) -> Self {
+        self.envelope = preset.parse().unwrap_or_else(|e: String| panic!("{}", e));
+        self
+    }
+
+    /// Strict mode, for catching client bugs during development. When enabled:
+    /// - a non-empty body must be sent with `Content-Type: application/json` (or a
+    ///   [`codec`](Self::codec)'s type), otherwise `415 Unsupported Media Type`;
+    /// - argument objects with fields the argument type doesn't declare, and any
+    ///   arguments sent to a command that takes none, fail with an error naming them;
+    /// - a query string sent with a body that isn't a JSON object, so it can't be
+    ///   merged into the arguments, is rejected with `400 Bad Request`.
+    ///
+    /// Off by default.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Render command results as annotated JSON5 (a comment naming the command,
+    /// unquoted keys, trailing commas) with `Content-Type: application/json5`,
+    /// for reading responses in devtools.
+    ///
+    /// Only takes effect in debug builds: with `debug_assertions` off, responses
+    /// are strict JSON regardless. Frontends calling `res.json()` can't parse this
+    /// output, so only enable it while inspecting responses by hand.
+    pub fn debug_json5(mut self, enabled: bool) -> Self {
+        self.debug_json5 = enabled;
+        self
+    }
+
+    /// `Access-Control-Allow-Origin` sent on every response, preflights included.
+    /// Defaults to `*`; set a fixed origin such as `"https://myapp.local"` to keep
+    /// pages from other origins from calling commands. Upgrade commands get it too
+    /// unless they set the header themselves.
+    ///
+    /// # Panics
+    /// If `origin` isn't a valid header value.
+    pub fn cors(mut self, origin: &str) -> Self {
+        self.cors = HeaderValue::from_str(origin)
+            .unwrap_or_else(|e| panic!("invalid CORS origin `{}`: {}", origin, e));
+        self
+    }
+
+    /// Also accept request bodies in `codec`'s format, answering in the same format.
+    /// Requests are matched by `Content-Type`; anything else is treated as JSON.
+    /// The `msgpack` and `cbor` features add [`MsgPack`](crate::MsgPack) and
+    /// [`Cbor`](crate::Cbor) by default. Binary [`CommandResponse::Bytes`] results
+    /// are sent as-is whatever the format.
+    pub fn codec(mut self, codec: impl Codec) -> Self {
+        self.codecs.push(Arc::new(codec));
+        self
+    }
+
+    /// Remove these leading path segments from command names before dispatch, so
+    /// `mado://api/v1/greet` calls `greet` with `strip_prefix = "api/v1"`.
+    /// Names without the prefix pass through unchanged.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        self.strip_prefix = (!prefix.is_empty()).then(|| prefix.to_string());
+        self
+    }
+
+    /// Only serve commands under `namespace` (`admin` itself and `admin/...`); any
+    /// other name, built-ins like `$introspect` included, gets `404 Not Found`.
+    /// For several schemes sharing the global registry, e.g. an `admin://` protocol
+    /// with `namespace = "admin"`. Applied after [`strip_prefix`](Self::strip_prefix).
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.trim_matches('/').to_string());
+        self
+    }
+
+    /// The codec for a request's `Content-Type`, if it isn't JSON.
+    fn codec_for(&self, headers: &HeaderMap) -> Option<Arc<dyn Codec>> {
+        let content_type = headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())?;
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        self.codecs
+            .iter()
+            .find(|codec| codec.content_type().eq_ignore_ascii_case(mime))
+            .cloned()
+    }
+}
+
+/// Codecs enabled by the `msgpack` and `cbor` features.
+fn default_codecs() -> Vec<Arc<dyn Codec>> {
+    #[allow(unused_mut)]
+    let mut codecs: Vec<Arc<dyn Codec>> = Vec::new();
+    #[cfg(feature = "msgpack")]
+    codecs.push(Arc::new(crate::MsgPack));
+    #[cfg(feature = "cbor")]
+    codecs.push(Arc::new(crate::Cbor));
+    codecs
+}
+
+impl fmt::Debug for ProtocolOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ProtocolOptions");
+        debug.field("scheme", &self.scheme);
+        #[cfg(not(feature = "sync-only"))]
+        debug.field("executor", &self.executor.as_ref().map(|_| ".."));
+        debug
+            .field("registry", &self.registry.as_ref().map(|r| r.names()))
+            .field("stack_size", &self.stack_size)
+            .field("inline_sync", &self.inline_sync)
+            .field("envelope", &self.envelope)
+            .field("strict", &self.strict)
+            .field("debug_json5", &self.debug_json5)
+            .field("cors", &self.cors)
+            .field(
+                "codecs",
+                &self.codecs.iter().map(|c| c.content_type()).collect::<Vec<_>>(),
+            )
+            .field("strip_prefix", &self.strip_prefix)
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+/// Handle a single custom-protocol request, calling `respond` once with the response.
+///
+/// Call it directly if you need to wrap the protocol handler with your own logic;
+/// see [`serve_webview`] to also tell handlers which webview sent the request.
+pub fn serve<R>(options: &ProtocolOptions, request: Request<Vec<u8>>, respond: R)
+where
+    R: FnOnce(Response<Vec<u8>>) + Send + 'static,
+{
+    serve_from(options, None, request, respond)
+}
+
+/// Like [`serve`], recording the id of the webview the request came from in
+/// [`CommandContext::webview_id`]. This is what `use_wry_cmd_protocol!` expands to.
+pub fn serve_webview<R>(
+    options: &ProtocolOptions,
+    webview_id: &str,
+    request: Request<Vec<u8>>,
+    respond: R,
+) where
+    R: FnOnce(Response<Vec<u8>>) + Send + 'static,
+{
+    serve_from(options, Some(webview_id.to_string()), request, respond)
+}
+
+fn serve_from<R>(
+    options: &ProtocolOptions,
+    webview_id: Option<String>,
+    request: Request<Vec<u8>>,
+    respond: R,
+) where
+    R: FnOnce(Response<Vec<u8>>) + Send + 'static,
+{
+    // Every response carries the configured CORS origin and the request id,
+    // taken from `X-Request-Id` or generated. Cross-origin pages can only read
+    // response headers listed in `Access-Control-Expose-Headers`.
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string);
+    let origin = options.cors.clone();
+    let request_id_header = HeaderValue::from_str(&request_id).ok();
+    let respond = move |mut resp: Response<Vec<u8>>| {
+        let headers = resp.headers_mut();
+        headers
+            .entry(http::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .or_insert(origin);
+        headers
+            .entry(http::header::ACCESS_CONTROL_EXPOSE_HEADERS)
+            .or_insert(HeaderValue::from_static("X-Request-Id, X-Warnings, Content-Range"));
+        if let Some(id) = request_id_header {
+            headers.entry(REQUEST_ID_HEADER).or_insert(id);
+        }
+        respond(resp);
+    };
+
+    let cmd = command_name(request.uri());
+    let cmd = match &options.strip_prefix {
+        Some(prefix) => strip_command_prefix(cmd, prefix),
+        None => cmd,
+    };
+    let lang = localize::preferred_language(request.headers());
+
+    // Names outside this protocol's namespace don't exist here
+    if let Some(namespace) = &options.namespace {
+        if !crate::in_namespace(&cmd, namespace) {
+            let error = DispatchError::UnknownCommand(normalize_command_name(&cmd));
+            respond(error_response(options, &lang, StatusCode::NOT_FOUND, error));
+            return;
+        }
+    }
+
+    // Upgrade commands take over the request as-is, preflight included
+    let normalized = normalize_command_name(&cmd);
+    let upgrade_handler = upgrade::find_upgrade(&normalized).filter(|_| options.registry.is_none());
+    if let Some(handler) = upgrade_handler {
+        if let Err(e) = allowlist::check_allowed(&normalized) {
+            let error = DispatchError::Unavailable(e);
+            respond(error_response(options, &lang, StatusCode::FORBIDDEN, error));
+            return;
+        }
+        spawn_command(options, move || handler(request, Box::new(respond)));
+        return;
+    }
+
+    // Runtime self-description: `scheme://_schema/<command>`
+    #[cfg(feature = "introspection")]
+    if let Some(target) = normalized.strip_prefix("_schema/") {
+        respond(schema_response(options, &lang, target));
+        return;
+    }
+
+    // An explicit registry's commands carry no metadata
+    let cmd_def = find_command(&cmd).filter(|_| options.registry.is_none());
+    let allowed_methods = cmd_def.map_or(DEFAULT_ALLOWED_METHODS, |c| c.allowed_methods());
+
+    // Handle CORS preflight, advertising only what the requested command accepts.
+    // Unknown (or runtime-registered) commands get the default set so the actual
+    // request still reaches the dispatcher and receives a JSON error.
+    if request.method() == Method::OPTIONS {
+        let mut allowed_headers = vec!["Content-Type", "X-Request-Id", "Range"];
+        if let Some(cmd_def) = cmd_def {
+            allowed_headers.extend_from_slice(cmd_def.required_headers);
+        }
+        let resp = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Access-Control-Allow-Methods", allowed_methods)
+            .header("Access-Control-Allow-Headers", allowed_headers.join(", "))
+            .body(Vec::new())
+            .unwrap();
+        respond(resp);
+        return;
+    }
+
+    // Only POST is allowed, plus GET for commands that opted in
+    let is_get = request.method() == Method::GET && cmd_def.map_or(false, |c| c.accepts_get());
+    if request.method() != Method::POST && !is_get {
+        let resp = Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("Allow", allowed_methods)
+            .body(b"Method Not Allowed".to_vec())
+            .unwrap();
+        respond(resp);
+        return;
+    }
+
+    // Reject requests missing any header the command requires
+    if let Some(cmd_def) = cmd_def {
+        if let Some(missing) = cmd_def
+            .required_headers
+            .iter()
+            .find(|h| !request.headers().contains_key(**h))
+        {
+            let status = if missing.eq_ignore_ascii_case("authorization") {
+                StatusCode::UNAUTHORIZED
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            let error = DispatchError::Unavailable(format!("Missing required header: {}", missing));
+            respond(error_response(options, &lang, status, error));
+            return;
+        }
+    }
+
+    // Bodies in a codec's format are answered in it too; anything else is JSON
+    let codec = options.codec_for(request.headers());
+    if options.strict && codec.is_none() {
+        if let Some((status, error)) = strict_violation(&request) {
+            respond(error_response(options, &lang, status, DispatchError::BadArgs(error)));
+            return;
+        }
+    }
+
+    // Parse args from body, then merge in the query string. With the
+    // `arbitrary_precision` feature, JSON numbers keep their exact digits here and
+    // reach typed args without an f64 round-trip. An empty body means `null`; a
+    // malformed one is rejected rather than silently becoming `null`
+    let request_bytes = request.body().len();
+    let empty = is_empty_body(request.body());
+    let parsed = match &codec {
+        _ if empty => Ok(Value::Null),
+        Some(codec) => codec
+            .decode(request.body())
+            .map_err(|e| format!("invalid {} body: {}", codec.content_type(), e)),
+        None => serde_json::from_slice(request.body())
+            .map_err(|e| format!("invalid JSON body: {}", e)),
+    };
+    let body = match parsed {
+        Ok(body) => body,
+        Err(error) => {
+            let error = DispatchError::BadArgs(error);
+            respond(error_response(options, &lang, StatusCode::BAD_REQUEST, error));
+            return;
+        }
+    };
+    let args = match merge_query(body, request.uri(), options.strict) {
+        Ok(args) => args,
+        Err(error) => {
+            let error = DispatchError::BadArgs(error);
+            respond(error_response(options, &lang, StatusCode::BAD_REQUEST, error));
+            return;
+        }
+    };
+    let ctx = CommandContext {
+        deny_unknown_fields: options.strict,
+        headers: header_map(request.headers()),
+        uri: request.uri().to_string(),
+        webview_id,
+        request_id: Some(request_id),
+        ..CommandContext::default()
+    };
+
+    // Detached commands are acknowledged right away with a job id and run unobserved
+    if cmd_def.map_or(false, |c| c.detach) {
+        let job_id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed).to_string();
+        let accepted = options
+            .envelope
+            .wrap(Ok(serde_json::json!({ "job_id": job_id })));
+        respond(json_response(StatusCode::ACCEPTED, &accepted));
+
+        let tag = ctx.log_tag();
+        let name = cmd.clone();
+        // Nobody is awaiting the result, so failures can only be reported here
+        let report = move |result: Result<Value, String>| {
+            if let Err(e) = result {
+                log::error!("Detached command {} (job {}) failed: {}{}", name, job_id, e, tag);
+            }
+        };
+        #[cfg(not(feature = "sync-only"))]
+        run_command(options, async move { report(handle_command_with(&cmd, args, ctx).await) });
+        #[cfg(feature = "sync-only")]
+        run_command(options, move || report(handle_command_with(&cmd, args, ctx)));
+        return;
+    }
+
+    // Metrics are keyed by the registered name, and only for commands that exist
+    let metered = match (cmd_def, &options.registry) {
+        (Some(cmd_def), _) => Some(cmd_def.name.to_string()),
+        (None, Some(registry)) => {
+            Some(normalized).filter(|name| registry.names().contains(&name.as_str()))
+        }
+        (None, None) => Some(normalized).filter(|name| crate::dynamic::find_dynamic(name).is_some()),
+    };
+
+    // Build and send the response once the command has produced its result
+    let envelope = options.envelope;
+    let returns_warnings = cmd_def.map_or(false, |c| c.returns_warnings);
+    let returns_unit = cmd_def.map_or(false, |c| c.returns_unit);
+    let error_status = cmd_def
+        .and_then(|c| c.error_status)
+        .and_then(|status| StatusCode::from_u16(status).ok());
+    let debug_json5 = cfg!(debug_assertions) && options.debug_json5;
+    let range = ctx.header("range").map(str::to_string);
+    let name = cmd.clone();
+    let finish = move |result: Result<CommandResponse, DispatchError>| {
+        let metered = metered.as_deref();
+        // The handler's chosen status for a success, or one matching the failure
+        let (status, result) = match result {
+            Ok(CommandResponse::Status { status, response }) => (status, Ok(*response)),
+            Ok(response) => (StatusCode::OK, Ok(response)),
+            Err(e) => (error_status_for(&e, error_status), Err(e)),
+        };
+
+        // Commands without a result answer a plain success with no body at all, unless
+        // the envelope promises a body on every response
+        if returns_unit && envelope == Envelope::Bare && status == StatusCode::OK && result.is_ok()
+        {
+            let resp = Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Vec::new())
+                .unwrap();
+            crate::record_payload_sizes(metered, request_bytes, 0);
+            respond(resp);
+            return;
+        }
+
+        // Binary results are sent as-is, without an envelope
+        let result_json = match result {
+            Ok(CommandResponse::File { content_type, path }) => {
+                // A handler's own status overrides any range
+                let range = range.as_deref().filter(|_| status == StatusCode::OK);
+                let resp = file_response(status, &content_type, &path, range);
+                crate::record_payload_sizes(metered, request_bytes, resp.body().len());
+                respond(resp);
+                return;
+            }
+            Ok(CommandResponse::Bytes { content_type, data }) => {
+                let resp = bytes_response(status, &content_type, data);
+                crate::record_payload_sizes(metered, request_bytes, resp.body().len());
+                respond(resp);
+                return;
+            }
+            // Pre-serialized JSON goes out without a `Value` round-trip, unless
+            // the warnings header, JSON5 rendering or a codec needs to look inside
+            Ok(CommandResponse::RawJson(raw))
+                if !returns_warnings && !debug_json5 && codec.is_none() =>
+            {
+                let resp = json_body_response(status, envelope.wrap_raw(raw));
+                crate::record_payload_sizes(metered, request_bytes, resp.body().len());
+                respond(resp);
+                return;
+            }
+            Ok(CommandResponse::Json(value)) => Ok(value),
+            Ok(response) => Ok(response.into_json()),
+            Err(e) => Err(localize::localize(&lang, &e)),
+        };
+
+        let warnings = match &result_json {
+            Ok(value) if returns_warnings => warnings_header(value),
+            _ => None,
+        };
+
+        // Wrap the result (and any error) into the configured envelope
+        let response_value = envelope.wrap_value(result_json);
+
+        let mut resp = match &codec {
+            Some(codec) => codec_response(status, codec.as_ref(), &response_value),
+            None if debug_json5 => json5_response(status, &name, &response_value),
+            None => json_response(status, &response_value),
+        };
+        if let Some(warnings) = warnings {
+            resp.headers_mut().insert("X-Warnings", warnings);
+        }
+        crate::record_payload_sizes(metered, request_bytes, resp.body().len());
+        respond(resp);
+    };
+
+    // A panicking command still gets an answer, rather than dropping the responder
+    // and leaving the frontend's `fetch` pending forever
+    let tag = ctx.log_tag();
+    let recover = move |cmd: &str, result: std::thread::Result<_>| {
+        result.unwrap_or_else(|payload| {
+            log::error!("Command {} panicked: {}{}", cmd, panic_message(&*payload), tag);
+            Err(DispatchError::Handler("internal panic".to_string()))
+        })
+    };
+
+    // Sync commands run right here when opted into, skipping the future and the
+    // command thread, unless they need the command thread's stack size
+    let inline = options.inline_sync && options.registry.is_none() && options.stack_size.is_none();
+    let sync_route = crate::sync_route(&cmd).filter(|_| inline);
+    if let Some(route) = sync_route {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| crate::run_sync(route, args, ctx)));
+        finish(recover(&cmd, result));
+        return;
+    }
+
+    // Anything else runs in the background, on the executor or a thread of its own
+    let registry = options.registry.clone();
+    #[cfg(not(feature = "sync-only"))]
+    run_command(options, async move {
+        let dispatch = async {
+            match registry {
+                Some(registry) => registry.dispatch_checked(&cmd, args).await,
+                None => handle_command_response(&cmd, args, ctx).await,
+            }
+        };
+        let result = AssertUnwindSafe(dispatch).catch_unwind().await;
+        finish(recover(&cmd, result));
+    });
+    #[cfg(feature = "sync-only")]
+    run_command(options, move || {
+        let dispatch = || match registry {
+            Some(registry) => registry.dispatch_checked(&cmd, args),
+            None => handle_command_response(&cmd, args, ctx),
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(dispatch));
+        finish(recover(&cmd, result));
+    });
+}
+
+/// The message a panic was raised with, for `panic!("...")` and `panic!("{}", ...)`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+/// The status a failed dispatch is sent with: `404` for unknown commands, `400` for bad
+/// arguments, `403` for unavailable ones, and `error_status` (or `500`) when the handler
+/// itself failed.
+fn error_status_for(error: &DispatchError, error_status: Option<StatusCode>) -> StatusCode {
+    match error {
+        DispatchError::UnknownCommand(_) => StatusCode::NOT_FOUND,
+        DispatchError::BadArgs(_) => StatusCode::BAD_REQUEST,
+        DispatchError::Unavailable(_) => StatusCode::FORBIDDEN,
+        DispatchError::Handler(_) | DispatchError::Structured(_) => {
+            error_status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Respond with one command's argument and result schemas.
+#[cfg(feature = "introspection")]
+fn schema_response(options: &ProtocolOptions, lang: &str, target: &str) -> Response<Vec<u8>> {
+    match find_command(target).and_then(|c| c.schema.map(|schema| (c.name, schema))) {
+        Some((name, schema)) => {
+            let mut value = schema();
+            value["command"] = Value::from(name);
+            json_response(StatusCode::OK, &options.envelope.wrap(Ok(value)))
+        }
+        None => {
+            let error = DispatchError::Handler(format!("No schema for command: {}", target));
+            error_response(options, lang, StatusCode::NOT_FOUND, error)
+        }
+    }
+}
+
+/// Run a command's future on the configured executor, or block on it on a new thread.
+#[cfg(not(feature = "sync-only"))]
+fn run_command<F>(options: &ProtocolOptions, fut: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match &options.executor {
+        Some(executor) => executor.spawn(fut.boxed()),
+        None => spawn_command(options, move || futures::executor::block_on(fut)),
+    }
+}
+
+/// Run a command on a new thread: `sync-only` builds have no executor.
+#[cfg(feature = "sync-only")]
+fn run_command<F>(options: &ProtocolOptions, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_command(options, f);
+}
+
+/// Run `f` on a new command thread, honoring the configured stack size.
+fn spawn_command<F>(options: &ProtocolOptions, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut thread = std::thread::Builder::new();
+    if let Some(stack_size) = options.stack_size {
+        thread = thread.stack_size(stack_size);
+    }
+    thread.spawn(f).expect("failed to spawn command thread");
+}
+
+/// Extract the command name from a request URI: `mado://greet` → `greet`,
+/// `http://mado.mycommands/greet/` → `mado.mycommands/greet`.
+fn command_name(uri: &Uri) -> String {
+    // 1. Extract host (authority) and path separately
+    let host = uri.authority().map(|a| a.as_str()).unwrap_or("");
+    let path = uri.path_and_query().map(|pq| pq.path()).unwrap_or("");
+
+    // 2. Trim any leading slash on the path
+    let path = path.trim_start_matches('/');
+
+    // 3. Build the command name
+    if host.is_empty() {
+        // no host, just path
+        path.to_string()
+    } else if path.is_empty() {
+        // host only
+        host.to_string()
+    } else {
+        // both host and path
+        format!("{}/{}", host, path)
+    }
+}
+
+/// `cmd` without the leading `prefix` segments (`api/v1/greet` → `greet`),
+/// or unchanged when it doesn't start with them.
+fn strip_command_prefix(cmd: String, prefix: &str) -> String {
+    match cmd.trim_start_matches('/').strip_prefix(prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.to_string(),
+        _ => cmd,
+    }
+}
+
+/// Header carrying the request id, read from requests and echoed on responses.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Characters escaped in `X-Warnings` entries, on top of non-ASCII bytes.
+const WARNING_ESCAPES: &AsciiSet = &CONTROLS.add(b',').add(b'%');
+
+/// The `X-Warnings` value for a serialized [`WithWarnings`](crate::WithWarnings),
+/// if it has any warnings.
+fn warnings_header(value: &Value) -> Option<HeaderValue> {
+    let warnings: Vec<String> = value
+        .get("warnings")?
+        .as_array()?
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|w| utf8_percent_encode(w, WARNING_ESCAPES).to_string())
+        .collect();
+    if warnings.is_empty() {
+        return None;
+    }
+    HeaderValue::from_str(&warnings.join(",")).ok()
+}
+
+/// Request headers as lowercase name → value, joining repeated headers with `", "`.
+/// Values that aren't valid UTF-8 are skipped.
+fn header_map(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut map: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        map.entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    map
+}
+
+/// Merge the query string into the body's arguments; see `use_wry_cmd_protocol!`.
+/// In `strict` mode, a query that can't be merged because the body isn't an object
+/// is an error rather than silently dropped.
+fn merge_query(body: Value, uri: &Uri, strict: bool) -> Result<Value, String> {
+    let Some(query) = query_args(uri) else {
+        return Ok(body);
+    };
+    match body {
+        Value::Null => Ok(Value::Object(query)),
+        Value::Object(mut body) => {
+            for (key, value) in query {
+                body.entry(key).or_insert(value);
+            }
+            Ok(Value::Object(body))
+        }
+        _ if strict => Err(
+            "query parameters can only be combined with an object body (or none)".to_string(),
+        ),
+        other => Ok(other),
+    }
+}
+
+/// Arguments from the query string: `?name=Alice&tag=a&tag=b` →
+/// `{ "name": "Alice", "tag": ["a", "b"] }`. Keys and values are percent-decoded
+/// (`+` as a space) and always strings; a repeated key collects its values in order.
+fn query_args(uri: &Uri) -> Option<Map<String, Value>> {
+    let query = uri.query().filter(|q| !q.is_empty())?;
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+    let mut args = Map::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = Value::String(decode(value));
+        match args.get_mut(&decode(key)) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None => {
+                args.insert(decode(key), value);
+            }
+        }
+    }
+    Some(args)
+}
+
+/// No body, or only whitespace: the command gets `null` either way.
+fn is_empty_body(body: &[u8]) -> bool {
+    body.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Strict-mode checks that run before the body is parsed.
+fn strict_violation(request: &Request<Vec<u8>>) -> Option<(StatusCode, String)> {
+    if !is_empty_body(request.body()) {
+        let content_type = request
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !mime.eq_ignore_ascii_case("application/json") {
+            return Some((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "expected Content-Type: application/json, got `{}`",
+                    content_type
+                ),
+            ));
+        }
+    }
+    None
+}
+
+/// Build a localized error response in the configured envelope.
+fn error_response(
+    options: &ProtocolOptions,
+    lang: &str,
+    status: StatusCode,
+    error: DispatchError,
+) -> Response<Vec<u8>> {
+    let error = localize::localize(lang, &error);
+    json_response(status, &options.envelope.wrap_value(Err(error)))
+}
+
+/// Build a JSON response.
+fn json_response(status: StatusCode, value: &Value) -> Response<Vec<u8>> {
+    json_body_response(status, serde_json::to_vec(value).unwrap_or_default())
+}
+
+/// Build a JSON response from an already-serialized body.
+fn json_body_response(status: StatusCode, body: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// Build a response in `codec`'s format.
+fn codec_response(status: StatusCode, codec: &dyn Codec, value: &Value) -> Response<Vec<u8>> {
+    match codec.encode(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("Content-Type", codec.content_type())
+            .body(body)
+            .unwrap(),
+        Err(e) => {
+            let error = format!("failed to encode {} response: {}", codec.content_type(), e);
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &Envelope::Bare.wrap(Err(error)),
+            )
+        }
+    }
+}
+
+/// Build a response carrying a command's raw bytes.
+fn bytes_response(status: StatusCode, content_type: &str, data: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", content_type)
+        .body(data)
+        .unwrap_or_else(|e| {
+            let error = format!("invalid Content-Type `{}`: {}", content_type, e);
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &Envelope::Bare.wrap(Err(error)),
+            )
+        })
+}
+
+/// The part of a file a `Range` header asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No range, or one this doesn't serve (several ranges, other units, bad
+    /// syntax): the whole file, as if the header weren't there
+    Whole,
+    /// First and last byte, inclusive
+    Part(u64, u64),
+    /// Starts past the end of the file
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=start-end`, `bytes=start-` or `bytes=-suffix` range
+/// against a file of `len` bytes, clamping its end to the file.
+fn byte_range(header: Option<&str>, len: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Whole,
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Whole;
+    };
+    let last = len.saturating_sub(1);
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), last),
+            Err(_) => return ByteRange::Whole,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, last),
+            Err(_) => return ByteRange::Whole,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(last)),
+            _ => return ByteRange::Whole,
+        },
+    };
+    if start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Part(start, end)
+    }
+}
+
+/// Build a response from a file: the whole of it, or for a `Range` just the requested
+/// bytes with `206 Partial Content` (`416` if the range starts past the end). Only
+/// the bytes sent are read.
+fn file_response(
+    status: StatusCode,
+    content_type: &str,
+    path: &Path,
+    range: Option<&str>,
+) -> Response<Vec<u8>> {
+    let read = || -> io::Result<Response<Vec<u8>>> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let (status, body, content_range) = match byte_range(range, len) {
+            ByteRange::Whole => {
+                let mut body = Vec::with_capacity(len as usize);
+                file.read_to_end(&mut body)?;
+                (status, body, None)
+            }
+            ByteRange::Part(start, end) => {
+                let mut body = vec![0; (end - start + 1) as usize];
+                file.seek(SeekFrom::Start(start))?;
+                file.read_exact(&mut body)?;
+                let content_range = format!("bytes {}-{}/{}", start, end, len);
+                (StatusCode::PARTIAL_CONTENT, body, Some(content_range))
+            }
+            ByteRange::Unsatisfiable => {
+                let content_range = format!("bytes */{}", len);
+                (StatusCode::RANGE_NOT_SATISFIABLE, Vec::new(), Some(content_range))
+            }
+        };
+        let mut resp = bytes_response(status, content_type, body);
+        let headers = resp.headers_mut();
+        headers.insert(http::header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(content_range) = content_range {
+            // Digits, spaces, `-`, `*` and `/` only, always a valid header value
+            headers.insert(http::header::CONTENT_RANGE, content_range.parse().unwrap());
+        }
+        Ok(resp)
+    };
+    read().unwrap_or_else(|e| {
+        let error = format!("failed to read {}: {}", path.display(), e);
+        json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &Envelope::Bare.wrap(Err(error)),
+        )
+    })
+}
+
+/// Build a debug-only JSON5 response; see [`ProtocolOptions::debug_json5`].
+fn json5_response(status: StatusCode, cmd: &str, value: &Value) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json5")
+        .body(crate::json5::to_json5(cmd, value).into_bytes())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges() {
+        let range = |header| byte_range(Some(header), 100);
+        assert_eq!(range("bytes=0-9"), ByteRange::Part(0, 9));
+        assert_eq!(range("bytes=90-"), ByteRange::Part(90, 99));
+        assert_eq!(range("bytes=-10"), ByteRange::Part(90, 99));
+        assert_eq!(range("bytes=50-500"), ByteRange::Part(50, 99));
+        assert_eq!(range("bytes=-500"), ByteRange::Part(0, 99));
+        assert_eq!(range("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        // Served whole, as if there were no header
+        assert_eq!(byte_range(None, 100), ByteRange::Whole);
+        assert_eq!(range("bytes=0-1,5-6"), ByteRange::Whole);
+        assert_eq!(range("items=0-1"), ByteRange::Whole);
+        assert_eq!(range("bytes=9-0"), ByteRange::Whole);
+        assert_eq!(range("bytes=x-"), ByteRange::Whole);
+    }
+
+    #[test]
+    fn strict_mode_treats_whitespace_bodies_as_empty() {
+        let request = |body: &str| Request::new(body.as_bytes().to_vec());
+        assert!(strict_violation(&request("")).is_none());
+        assert!(strict_violation(&request(" \r\n")).is_none());
+        let (status, _) = strict_violation(&request("{}")).unwrap();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn empty_files_satisfy_no_range() {
+        assert_eq!(byte_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=-5"), 0), ByteRange::Unsatisfiable);
+    }
+}
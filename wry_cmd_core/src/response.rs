@@ -0,0 +1,112 @@
+//! What a command handler produces: JSON, or raw bytes with a content type.
+
+use http::StatusCode;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// A command's successful result.
+///
+/// `#[command]` wraps serializable return values in [`CommandResponse::Json`]
+/// automatically; return a `CommandResponse` yourself to send binary data
+/// (thumbnails, file contents) without base64-encoding it into JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandResponse {
+    /// Sent as JSON, in the configured envelope.
+    Json(Value),
+    /// Already-serialized JSON, sent in the configured envelope without going
+    /// through a [`Value`]. `#[command]` produces this for serializable returns,
+    /// so large results are written straight to the response body.
+    RawJson(Vec<u8>),
+    /// Sent as-is, with this `Content-Type` and no envelope.
+    Bytes { content_type: String, data: Vec<u8> },
+    /// A file on disk, sent as-is with this `Content-Type` and no envelope;
+    /// see [`CommandResponse::file`].
+    File { content_type: String, path: PathBuf },
+    /// Another response sent with a status other than `200 OK`;
+    /// see [`CommandResponse::with_status`].
+    Status {
+        status: StatusCode,
+        response: Box<CommandResponse>,
+    },
+}
+
+impl CommandResponse {
+    /// A binary response, e.g. `CommandResponse::bytes("image/png", png)`.
+    pub fn bytes(content_type: impl Into<String>, data: Vec<u8>) -> Self {
+        CommandResponse::Bytes {
+            content_type: content_type.into(),
+            data,
+        }
+    }
+
+    /// A file on disk, e.g. `CommandResponse::file("video/mp4", path)`. The protocol
+    /// reads it when sending the response and honors `Range` requests, answering
+    /// `206 Partial Content` with only the requested bytes, so a `<video>` can seek
+    /// through a large file without it ever being read whole.
+    pub fn file(content_type: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        CommandResponse::File {
+            content_type: content_type.into(),
+            path: path.into(),
+        }
+    }
+
+    /// Send this response with `status` instead of `200 OK`, e.g.
+    /// `CommandResponse::from(value).with_status(StatusCode::CREATED)`.
+    /// Only the protocol looks at it; `Value`-based dispatch ignores the status.
+    pub fn with_status(self, status: StatusCode) -> Self {
+        let response = match self {
+            CommandResponse::Status { response, .. } => response,
+            other => Box::new(other),
+        };
+        CommandResponse::Status { status, response }
+    }
+
+    /// The response as JSON, for callers of the `Value`-based dispatch functions.
+    /// Raw JSON is parsed back; bytes (and a file's contents, or `null` if it can't be
+    /// read) become an array of numbers, as serde serializes a `Vec<u8>`.
+    pub fn into_json(self) -> Value {
+        match self {
+            CommandResponse::Json(value) => value,
+            CommandResponse::RawJson(raw) => serde_json::from_slice(&raw).unwrap_or_default(),
+            CommandResponse::Bytes { data, .. } => Value::from(data),
+            CommandResponse::File { path, .. } => {
+                std::fs::read(path).map(Value::from).unwrap_or_default()
+            }
+            CommandResponse::Status { response, .. } => response.into_json(),
+        }
+    }
+}
+
+/// Largest integer JavaScript numbers hold exactly (`Number.MAX_SAFE_INTEGER`).
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Turn every integer in `value` outside ±(2^53 - 1) into a decimal string, so
+/// frontends parsing the JSON don't silently round IDs stored as `u64`/`i64`.
+/// Smaller integers and floats are left as numbers.
+/// `#[command(bigint_as_string)]` applies this to the command's result.
+pub fn bigints_as_strings(value: Value) -> Value {
+    match value {
+        Value::Number(n) => {
+            let unsafe_int = n.as_u64().map_or(false, |u| u > MAX_SAFE_INTEGER)
+                || n.as_i64().map_or(false, |i| i.unsigned_abs() > MAX_SAFE_INTEGER);
+            if unsafe_int {
+                Value::String(n.to_string())
+            } else {
+                Value::Number(n)
+            }
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(bigints_as_strings).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, bigints_as_strings(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+impl From<Value> for CommandResponse {
+    fn from(value: Value) -> Self {
+        CommandResponse::Json(value)
+    }
+}
@@ -0,0 +1,83 @@
+//! Registry-wide smoke test: call every command with `null` and watch for panics.
+
+use crate::{Command, CommandContext};
+use serde_json::Value;
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+/// Call every registered command's handler with `null` arguments, checking that
+/// none of them panics or runs longer than `timeout`.
+///
+/// Returning an error (e.g. failing to deserialize `null`) is fine; this only
+/// catches wiring bugs. Commands marked `#[command(no_smoke_test)]` are skipped.
+/// Returns how many commands ran, or a report of every failure.
+/// Usually called through [`smoke_test!`](crate::smoke_test).
+pub fn run_smoke_tests(timeout: Duration) -> Result<usize, String> {
+    let mut ran = 0;
+    let mut failures = Vec::new();
+    for cmd_def in inventory::iter::<Command> {
+        if !cmd_def.smoke_test {
+            continue;
+        }
+        ran += 1;
+
+        let (tx, rx) = mpsc::channel();
+        let handler = cmd_def.handler;
+        std::thread::spawn(move || {
+            #[cfg(not(feature = "sync-only"))]
+            let result =
+                futures::executor::block_on(handler(CommandContext::default(), Value::Null));
+            #[cfg(feature = "sync-only")]
+            let result = handler(CommandContext::default(), Value::Null);
+            let _ = tx.send(result);
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(_) => {}
+            // the sender is only dropped without sending if the handler panicked
+            Err(RecvTimeoutError::Disconnected) => {
+                failures.push(format!("{}: panicked", cmd_def.name));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                failures.push(format!("{}: timed out after {:?}", cmd_def.name, timeout));
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(ran)
+    } else {
+        Err(format!(
+            "{} of {} commands failed the smoke test:\n{}",
+            failures.len(),
+            ran,
+            failures.join("\n")
+        ))
+    }
+}
+
+/// Generate a `#[test]` that runs [`run_smoke_tests`] over every registered command.
+///
+/// Opt-in: invoke it once at the root of the crate whose commands should be
+/// checked. The timeout per command defaults to 5 seconds.
+///
+/// ```rust,ignore
+/// wry_cmd::smoke_test!();
+/// wry_cmd::smoke_test!(timeout_ms = 500);
+/// ```
+#[macro_export]
+macro_rules! smoke_test {
+    () => {
+        $crate::smoke_test!(timeout_ms = 5000);
+    };
+    (timeout_ms = $timeout_ms:expr $(,)?) => {
+        #[cfg(test)]
+        #[test]
+        fn wry_cmd_smoke_test() {
+            let timeout = ::std::time::Duration::from_millis($timeout_ms);
+            if let Err(report) = $crate::run_smoke_tests(timeout) {
+                panic!("{}", report);
+            }
+        }
+    };
+}
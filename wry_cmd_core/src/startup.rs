@@ -0,0 +1,60 @@
+//! One-time async initialization that runs before the first command.
+
+use futures::{
+    future::{BoxFuture, Shared},
+    Future, FutureExt,
+};
+use once_cell::sync::Lazy;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+static STARTUP: Lazy<Mutex<Option<Shared<BoxFuture<'static, ()>>>>> =
+    Lazy::new(|| Mutex::new(None));
+static STARTED: AtomicBool = AtomicBool::new(true);
+
+/// Register an async initializer (open a database, read config, call `set_state`, ...)
+/// to run once before the first command is dispatched.
+///
+/// Ordering guarantees:
+/// - `init` is not called until the first command is dispatched, and is called at most once.
+/// - That command, and every command dispatched while `init` is still running,
+///   waits for it to complete before its handler runs.
+/// - Once `init` has completed, commands skip this step entirely.
+///
+/// Call it before building the webview. Registering a new initializer replaces
+/// one that hasn't run yet, and re-arms the hook if one already ran.
+pub fn on_startup<F, Fut>(init: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let fut = async move { init().await }.boxed().shared();
+    *STARTUP.lock().unwrap() = Some(fut);
+    STARTED.store(false, Ordering::Release);
+}
+
+/// Whether commands can run right away, without waiting for a startup hook.
+pub(crate) fn is_started() -> bool {
+    STARTED.load(Ordering::Acquire)
+}
+
+/// Delay `fut` until the startup hook (if any) has completed.
+pub(crate) fn after_startup<T: Send + 'static>(
+    fut: BoxFuture<'static, T>,
+) -> BoxFuture<'static, T> {
+    if STARTED.load(Ordering::Acquire) {
+        return fut;
+    }
+    let init = STARTUP.lock().unwrap().clone();
+    match init {
+        Some(init) => async move {
+            init.await;
+            STARTED.store(true, Ordering::Release);
+            fut.await
+        }
+        .boxed(),
+        None => fut,
+    }
+}
@@ -0,0 +1,77 @@
+//! Application state registered at runtime and handed to commands by type.
+
+use crate::{CommandContext, FromContext};
+use once_cell::sync::Lazy;
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+static STATE: Lazy<RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Register `value` as the state of type `T`, replacing any previous value of that type.
+///
+/// Call it before building the webview (or from an [`on_startup`](crate::on_startup)
+/// hook). `#[commands]` impls read their instance from here, so an
+/// `impl MyCommands for MyApp` needs `set_state(MyApp::new(config))`; free commands
+/// can take a [`State<T>`] parameter.
+pub fn set_state<T: Send + Sync + 'static>(value: T) {
+    STATE
+        .write()
+        .unwrap()
+        .insert(TypeId::of::<T>(), Arc::new(value));
+}
+
+/// Register `value` behind a lock, as the state of type `std::sync::RwLock<T>`.
+///
+/// `#[commands]` impls with any `&mut self` method read their instance from here
+/// instead of [`set_state`]: their `&self` methods run under a read lock, side by
+/// side, and their `&mut self` methods under the write lock, one call at a time.
+/// Impls with only `&self` methods share the instance without locking.
+pub fn set_state_mutex<T: Send + Sync + 'static>(value: T) {
+    set_state(std::sync::RwLock::new(value));
+}
+
+/// The registered state of type `T`, if any.
+pub fn state<T: Send + Sync + 'static>() -> Option<Arc<T>> {
+    let state = STATE.read().unwrap().get(&TypeId::of::<T>())?.clone();
+    state.downcast().ok()
+}
+
+/// Extractor for state registered with [`set_state`].
+///
+/// ```rust,ignore
+/// #[command]
+/// fn count(db: State<Db>) -> usize {
+///     db.count()
+/// }
+/// ```
+///
+/// Fails the command with an error if no `T` has been registered.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> FromContext for State<T> {
+    fn from_context(_ctx: &CommandContext) -> Result<Self, String> {
+        state::<T>()
+            .map(State)
+            .ok_or_else(|| format!("state not set: call set_state::<{}>() first", type_name::<T>()))
+    }
+}
@@ -0,0 +1,45 @@
+//! Byte-stream results for commands marked `#[command(stream)]`.
+//!
+//! wry's responder takes the whole body at once, so there is no chunked transfer:
+//! the stream is collected into a single [`CommandResponse::Bytes`], up to the
+//! limit set with [`set_stream_limit`]. Streaming still lets a command produce
+//! its output incrementally instead of building one big buffer itself.
+
+use crate::{CommandResponse, DispatchError};
+use futures::{Stream, StreamExt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default upper bound on a collected stream, in bytes (64 MiB).
+pub const DEFAULT_STREAM_LIMIT: usize = 64 * 1024 * 1024;
+
+static STREAM_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_STREAM_LIMIT);
+
+/// Set the most bytes a `#[command(stream)]` command may produce.
+/// A stream going past it is dropped and the command fails.
+pub fn set_stream_limit(bytes: usize) {
+    STREAM_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Collect a command's byte stream into a binary response with `content_type`.
+#[doc(hidden)]
+pub async fn collect_stream<S>(
+    content_type: &str,
+    stream: S,
+) -> Result<CommandResponse, DispatchError>
+where
+    S: Stream<Item = Vec<u8>>,
+{
+    let limit = STREAM_LIMIT.load(Ordering::Relaxed);
+    let mut stream = Box::pin(stream);
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        if data.len() + chunk.len() > limit {
+            return Err(DispatchError::Handler(format!(
+                "stream exceeded the {} byte limit",
+                limit
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(CommandResponse::bytes(content_type, data))
+}
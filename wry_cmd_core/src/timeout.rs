@@ -0,0 +1,111 @@
+//! Deadlines for commands marked `#[command(timeout_ms = ...)]`.
+//!
+//! Every deadline is kept by a single timer thread rather than a runtime timer, so
+//! timeouts work the same with or without the `tokio` feature. A command finishing
+//! in time cancels its deadline, so pending deadlines never outnumber running
+//! commands. Only async commands can be cut short: a sync handler runs to completion
+//! before the deadline is ever checked.
+
+use crate::{CommandResponse, DispatchError};
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Either},
+    FutureExt,
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Pending deadlines, earliest first, each firing a oneshot when it passes.
+struct Timer {
+    deadlines: Mutex<Deadlines>,
+    changed: Condvar,
+}
+
+struct Deadlines {
+    /// Keyed by deadline, then by id to tell apart deadlines falling on the same instant
+    pending: BTreeMap<(Instant, u64), oneshot::Sender<()>>,
+    next_id: u64,
+}
+
+static TIMER: Lazy<&'static Timer> = Lazy::new(|| {
+    let timer: &'static Timer = Box::leak(Box::new(Timer {
+        deadlines: Mutex::new(Deadlines {
+            pending: BTreeMap::new(),
+            next_id: 0,
+        }),
+        changed: Condvar::new(),
+    }));
+    thread::Builder::new()
+        .name("wry_cmd-timeouts".to_string())
+        .spawn(move || timer.run())
+        .expect("failed to spawn the timeout thread");
+    timer
+});
+
+impl Timer {
+    /// Fire each deadline as it passes, sleeping until the next one (or a new one).
+    fn run(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let next = deadlines.pending.keys().next().copied();
+            deadlines = match next {
+                Some(key) if key.0 <= now => {
+                    if let Some(tx) = deadlines.pending.remove(&key) {
+                        let _ = tx.send(());
+                    }
+                    deadlines
+                }
+                Some((at, _)) => self.changed.wait_timeout(deadlines, at - now).unwrap().0,
+                None => self.changed.wait(deadlines).unwrap(),
+            };
+        }
+    }
+
+    fn schedule(&'static self, at: Instant, tx: oneshot::Sender<()>) -> Scheduled {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let key = (at, deadlines.next_id);
+        deadlines.next_id += 1;
+        deadlines.pending.insert(key, tx);
+        // The thread may be sleeping until a later deadline
+        self.changed.notify_one();
+        Scheduled { timer: self, key }
+    }
+}
+
+/// A scheduled deadline, cancelled on drop.
+struct Scheduled {
+    timer: &'static Timer,
+    key: (Instant, u64),
+}
+
+impl Drop for Scheduled {
+    fn drop(&mut self) {
+        self.timer.deadlines.lock().unwrap().pending.remove(&self.key);
+    }
+}
+
+/// Race `fut` against `timeout`, dropping it and failing with
+/// `"command timed out"` if the deadline passes first.
+pub(crate) fn with_timeout(
+    fut: BoxFuture<'static, Result<CommandResponse, DispatchError>>,
+    timeout: Duration,
+) -> BoxFuture<'static, Result<CommandResponse, DispatchError>> {
+    let (tx, rx) = oneshot::channel::<()>();
+    let scheduled = TIMER.schedule(Instant::now() + timeout, tx);
+    future::select(fut, rx)
+        .map(move |either| {
+            // Finished (either way): the deadline has nothing left to cut short
+            drop(scheduled);
+            match either {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => Err(DispatchError::Handler("command timed out".to_string())),
+            }
+        })
+        .boxed()
+}
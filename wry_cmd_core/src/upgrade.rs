@@ -0,0 +1,47 @@
+//! Upgrade commands: handlers that take over the raw request and its responder.
+
+use http::{Request, Response};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+/// Sends the one response for an upgraded request. May be called from any thread.
+pub type Responder = Box<dyn FnOnce(Response<Vec<u8>>) + Send>;
+
+/// Type alias for upgrade handler functions.
+pub type UpgradeHandler = fn(Request<Vec<u8>>, Responder);
+
+/// A command registered with `#[command(upgrade)]`.
+///
+/// # Limitations
+///
+/// wry's custom protocols are request/response only. A responder can be called
+/// exactly once, with the complete body, so there is no real connection upgrade:
+/// no WebSocket handshake, no chunked streaming, no way to push to the frontend
+/// without a request to answer. What an upgrade handler *can* do is keep the
+/// responder and answer later, from any thread, which is enough for long-polling:
+///
+/// - the frontend keeps one request pending (e.g. `POST mado://terminal/poll`);
+/// - the handler parks its responder and answers once output is available;
+/// - the frontend immediately sends the next poll, and sends input as separate requests.
+///
+/// Every exchange still pays a full request round-trip, and a parked responder
+/// ties up a request slot in the webview until it is answered.
+pub struct UpgradeCommand {
+    pub name: &'static str,
+    pub handler: UpgradeHandler,
+}
+
+inventory::collect!(UpgradeCommand);
+
+static UPGRADES: Lazy<HashMap<&'static str, UpgradeHandler>> = Lazy::new(|| {
+    let mut upgrades = HashMap::new();
+    for cmd_def in inventory::iter::<UpgradeCommand> {
+        upgrades.entry(cmd_def.name).or_insert(cmd_def.handler);
+    }
+    upgrades
+});
+
+/// Look up an upgrade handler by normalized command name.
+pub(crate) fn find_upgrade(cmd: &str) -> Option<UpgradeHandler> {
+    UPGRADES.get(cmd).copied()
+}
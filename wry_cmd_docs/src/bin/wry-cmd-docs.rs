@@ -0,0 +1,78 @@
+//! Generate command docs on demand, without a build script:
+//!
+//! ```text
+//! wry-cmd-docs --src src [--src other/src ...] --out docs/commands [--check]
+//! ```
+//!
+//! With `--check`, nothing is written: the command fails if the docs are stale.
+
+use std::{path::PathBuf, process::ExitCode};
+
+const USAGE: &str = "usage: wry-cmd-docs --src <dir> [--src <dir> ...] --out <dir> [--check]";
+
+fn main() -> ExitCode {
+    let mut src_dirs: Vec<PathBuf> = Vec::new();
+    let mut out_dir: Option<PathBuf> = None;
+    let mut check = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--src" | "--out" => {
+                let Some(value) = args.next() else {
+                    eprintln!("{} needs a directory\n{}", arg, USAGE);
+                    return ExitCode::FAILURE;
+                };
+                if arg == "--src" {
+                    src_dirs.push(value.into());
+                } else {
+                    out_dir = Some(value.into());
+                }
+            }
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            other => {
+                eprintln!("unexpected argument `{}`\n{}", other, USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(out_dir) = out_dir.filter(|_| !src_dirs.is_empty()) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    if check {
+        return match wry_cmd_docs::generate_docs_check(&src_dirs, &out_dir) {
+            Ok(()) => {
+                println!("Docs under {} are up to date", out_dir.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let result = wry_cmd_docs::generate_docs(&src_dirs, &out_dir)
+        .and_then(|()| wry_cmd_docs::count_commands(&src_dirs));
+    match result {
+        Ok((services, commands)) => {
+            println!(
+                "Documented {} command(s) in {} service(s) under {}",
+                commands,
+                services,
+                out_dir.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to generate docs: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
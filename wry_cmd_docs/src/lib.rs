@@ -1,5 +1,12 @@
 //! Auto-generate one Markdown file per service listing its commands (with links)
-//! and referenced structs (with field docs).
+//! and referenced structs (with field docs), a JSON Schema of the whole
+//! command surface with [`generate_schema`], TypeScript declarations with
+//! [`generate_typescript`], an OpenAPI 3.0 spec with [`generate_openapi`], or a
+//! JSON manifest for tooling with [`generate_manifest`].
+//! [`generate_docs_combined`] writes all services into one file, and with the
+//! `watch` feature `generate_docs_watch` keeps the docs up to date while you edit.
+//! The `wry-cmd-docs` binary runs [`generate_docs`] on demand:
+//! `wry-cmd-docs --src src --out docs/commands`.
 //!
 //! # Example (in build.rs)
 //!
@@ -16,167 +23,776 @@
 //!     println!("cargo:rerun-if-changed=src");
 //! }
 //! ```
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use quote::ToTokens;
-use quote::quote;
 use syn::{
-    Attribute, Expr, ExprLit, Field, File, FnArg, ImplItem, ImplItemFn, Item, ItemFn, ItemImpl,
-    ItemStruct, Lit, MetaNameValue, ReturnType, parse_file, punctuated::Punctuated, token::Comma,
+    Attribute, Expr, ExprLit, Field, Fields, File, FnArg, Ident, ImplItem, ImplItemFn, Item,
+    ItemEnum, ItemFn, ItemImpl, ItemStruct, Lit, Meta, MetaNameValue, PatType, ReturnType,
+    parse_file, punctuated::Punctuated, token::Comma,
 };
 use walkdir::WalkDir;
 
+mod manifest;
+mod openapi;
+mod schema;
+mod typescript;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use manifest::generate_manifest;
+pub use openapi::generate_openapi;
+pub use schema::generate_schema;
+pub use typescript::generate_typescript;
+#[cfg(feature = "watch")]
+pub use watch::generate_docs_watch;
+
 struct CommandDoc {
     service: String,
     name: String,
     args: Option<String>,
     ret: Option<String>,
     description: String,
+    required_headers: Vec<String>,
+    /// Source file (relative to the crate root when possible) and 1-based line
+    file: String,
+    line: usize,
+    /// Module path the command was found in, from the file path and inline `mod`s
+    module: Vec<String>,
+    detach: bool,
+    /// Takes the raw request and responder; no JSON args or return
+    upgrade: bool,
+    /// Parameters filled by the server rather than sent by the caller
+    injected: Vec<String>,
+    aliases: Vec<String>,
+    alias_deprecated_since: Option<String>,
+    alias_removed_in: Option<String>,
+    /// `#[command(deprecated = "...")]`: why, and what to call instead
+    deprecated: Option<String>,
+    /// `#[command(example = "...")]`: a sample JSON body
+    example: Option<String>,
 }
 
 struct StructDoc {
     name: String,
     description: String,
-    fields: Vec<(String, String, String)>, // (field_name, field_type, field_doc)
+    fields: Vec<FieldDoc>,
+    /// Tuple struct: fields are positional (`0`, `1`, ...). A newtype is sent as
+    /// its inner value, a longer tuple struct as a JSON array.
+    tuple: bool,
 }
 
-/// For each service (and for free commands), generate `<service>.md` under `out_dir`.
-pub fn generate_docs(
-    src_dirs: &[impl AsRef<Path>],
-    out_dir: impl AsRef<Path>,
-) -> Result<(), Box<dyn std::error::Error>> {
+struct FieldDoc {
+    /// Name on the wire, after `#[serde(rename...)]`
+    name: String,
+    /// Name in Rust, when it differs from the wire name
+    rust_name: Option<String>,
+    ty: String,
+    doc: String,
+}
+
+struct EnumDoc {
+    name: String,
+    description: String,
+    tagging: Tagging,
+    variants: Vec<VariantDoc>,
+}
+
+/// How serde represents an enum on the wire
+enum Tagging {
+    /// The default: `{ "Variant": payload }`, or `"Variant"` for unit variants
+    External,
+    /// `#[serde(tag = "...")]`: the tag sits beside the variant's fields
+    Internal(String),
+    /// `#[serde(tag = "...", content = "...")]`
+    Adjacent(String, String),
+    /// `#[serde(untagged)]`: just the payload
+    Untagged,
+}
+
+struct VariantDoc {
+    name: String,
+    kind: VariantKind,
+    description: String,
+}
+
+enum VariantKind {
+    Unit,
+    /// Field types, in order
+    Tuple(Vec<String>),
+    /// (field_name, field_type)
+    Struct(Vec<(String, String)>),
+}
+
+/// Everything [`collect`] finds: commands, then structs and enums by name
+type Collected = (
+    Vec<CommandDoc>,
+    HashMap<String, StructDoc>,
+    HashMap<String, EnumDoc>,
+);
+
+/// Parse every `.rs` file under `src_dirs`, collecting commands, structs & enums
+fn collect(src_dirs: &[impl AsRef<Path>]) -> Result<Collected, Box<dyn std::error::Error>> {
     let mut cmds = Vec::new();
     let mut structs = HashMap::<String, StructDoc>::new();
-
-    // 1) Parse all files, collect commands & structs
+    let mut enums = HashMap::<String, EnumDoc>::new();
     for src in src_dirs {
+        // Walk in file-name order so the output doesn't depend on the filesystem
         for entry in WalkDir::new(src.as_ref())
+            .sort_by_file_name()
             .into_iter()
             .filter_map(Result::ok)
             .filter(|e| e.path().extension().map_or(false, |ext| ext == "rs"))
         {
             let text = fs::read_to_string(entry.path())?;
             let ast: File = parse_file(&text)?;
-            collect_commands(&ast.items, &mut cmds)?;
+            let first_new = cmds.len();
+            let rel = entry.path().strip_prefix(src.as_ref()).unwrap_or(entry.path());
+            collect_commands(&ast.items, entry.path(), &file_module(rel), &mut cmds)?;
+
+            // Record locations relative to the crate root (the source dir's parent)
+            let root = src.as_ref().parent().unwrap_or(Path::new(""));
+            let file = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            let file = file.to_string_lossy().replace('\\', "/");
+            for cmd in &mut cmds[first_new..] {
+                cmd.file = file.clone();
+            }
             collect_structs(&ast.items, &mut structs)?;
+            collect_enums(&ast.items, &mut enums);
         }
     }
+    Ok((cmds, structs, enums))
+}
 
-    // 2) Group commands by service
-    let mut by_service: HashMap<String, Vec<CommandDoc>> = HashMap::new();
-    for cmd in cmds {
-        by_service.entry(cmd.service.clone()).or_default().push(cmd);
+/// Module path for a source file relative to its source dir:
+/// `files.rs` → `[files]`, `media/mod.rs` → `[media]`, `lib.rs` → `[]`.
+fn file_module(rel: &Path) -> Vec<String> {
+    let mut module: Vec<String> = rel
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    match module.last().map(String::as_str) {
+        Some("mod") => {
+            module.pop();
+        }
+        Some("lib" | "main") if module.len() == 1 => {
+            module.pop();
+        }
+        _ => {}
     }
+    module
+}
+
+/// Options for [`generate_docs_with`].
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Document free `#[command]` functions under their innermost module
+    /// (`mod files { ... }` or `src/files.rs`) as a virtual service, instead of
+    /// collecting them all under "Free Commands". Runtime names are unaffected.
+    pub group_free_by_module: bool,
+    /// Also write a navigation file listing every generated page, in this format.
+    pub nav: Option<NavFormat>,
+    /// Mirror the source tree in the output: pages for commands in `src/media/player.rs`
+    /// go under `media/`, and that file's free commands become `media/player.md`
+    /// (implies [`group_free_by_module`](Self::group_free_by_module)).
+    /// Ignored by [`generate_docs_combined_with`].
+    pub nest_by_module: bool,
+    /// Custom protocol scheme used in the `fetch` snippets rendered for
+    /// `#[command(example = "...")]`. Defaults to `"app"`.
+    pub scheme: Option<String>,
+    /// Also draw a Mermaid graph of the IPC surface, linking each service to its
+    /// commands and each command to the structs and enums it uses: written to
+    /// `commands.mmd` by [`generate_docs_with`], and as a fenced `mermaid` block
+    /// under the table of contents by [`generate_docs_combined_with`].
+    pub mermaid: bool,
+}
+
+/// Navigation file formats [`generate_docs_with`] can emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavFormat {
+    /// mdBook's `SUMMARY.md`: a `# Summary` heading and one link per page.
+    MdBookSummary,
+}
+
+/// For each service (and for free commands), generate `<service>.md` under `out_dir`.
+pub fn generate_docs(
+    src_dirs: &[impl AsRef<Path>],
+    out_dir: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    generate_docs_with(src_dirs, out_dir, &GenerateOptions::default())
+}
 
-    // 3) Ensure output directory
+/// Like [`generate_docs`], with explicit [`GenerateOptions`].
+pub fn generate_docs_with(
+    src_dirs: &[impl AsRef<Path>],
+    out_dir: impl AsRef<Path>,
+    options: &GenerateOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
     let out = out_dir.as_ref();
     fs::create_dir_all(out)?;
+    for (path, contents) in render_pages(src_dirs, options)? {
+        let path = out.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
 
-    // 4) For each service, emit a file
-    for (service, mut list) in by_service {
-        // sort commands by name
-        list.sort_by(|a, b| a.name.cmp(&b.name));
+/// Check that `out_dir` holds exactly what [`generate_docs`] would write, like
+/// `cargo fmt --check`: `Ok(())` when every page is up to date, otherwise an error
+/// listing the missing or stale files, and any `.md` or `.mmd` file under `out_dir`
+/// that wouldn't be generated anymore (e.g. the page of a removed service).
+/// Nothing is written.
+pub fn generate_docs_check(
+    src_dirs: &[impl AsRef<Path>],
+    out_dir: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    generate_docs_check_with(src_dirs, out_dir, &GenerateOptions::default())
+}
 
-        // determine filename and title
-        let (filename, title) = if service == "_free_" {
-            ("free_commands.md".to_string(), "Free Commands".to_string())
+/// Like [`generate_docs_check`], with explicit [`GenerateOptions`].
+pub fn generate_docs_check_with(
+    src_dirs: &[impl AsRef<Path>],
+    out_dir: impl AsRef<Path>,
+    options: &GenerateOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let out = out_dir.as_ref();
+    let pages = render_pages(src_dirs, options)?;
+    let display = |path: &Path| path.to_string_lossy().replace('\\', "/");
+    let mut stale = Vec::new();
+    for (path, contents) in &pages {
+        if fs::read(out.join(path)).ok().as_deref() != Some(contents.as_bytes()) {
+            stale.push(display(path.as_path()));
+        }
+    }
+
+    // Leftover pages would survive regeneration, since nothing deletes them
+    let generated: HashSet<&Path> = pages.iter().map(|(path, _)| path.as_path()).collect();
+    for entry in WalkDir::new(out).into_iter().filter_map(Result::ok) {
+        let path = entry.path().strip_prefix(out).unwrap_or(entry.path());
+        let is_doc = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("md" | "mmd")
+        );
+        if entry.file_type().is_file() && is_doc && !generated.contains(path) {
+            stale.push(format!("{} (no longer generated)", display(path)));
+        }
+    }
+
+    if stale.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("command docs are out of date: {}", stale.join(", ")).into())
+    }
+}
+
+/// Every file [`generate_docs_with`] writes, as paths relative to the output
+/// directory and their contents
+fn render_pages(
+    src_dirs: &[impl AsRef<Path>],
+    options: &GenerateOptions,
+) -> Result<Vec<(PathBuf, String)>, Box<dyn std::error::Error>> {
+    // 1) Parse all files, collect commands, structs & enums
+    let (cmds, structs, enums) = collect(src_dirs)?;
+
+    // 2) Split commands by output directory (the source file's module path when
+    //    nesting), then group each directory's commands by service
+    let mut by_dir: BTreeMap<Vec<String>, Vec<CommandDoc>> = BTreeMap::new();
+    for cmd in cmds {
+        let dir = if options.nest_by_module {
+            cmd.module[..cmd.module.len().saturating_sub(1)].to_vec()
         } else {
-            (format!("{}.md", service.to_lowercase()), service.clone())
+            Vec::new()
         };
+        by_dir.entry(dir).or_default().push(cmd);
+    }
+    let group_options = GenerateOptions {
+        group_free_by_module: options.group_free_by_module || options.nest_by_module,
+        ..options.clone()
+    };
 
-        let mut md = String::new();
-        md.push_str(&format!("# {} Commands\n\n", title));
-
-        // index table
-        md.push_str("| Command | Args | Return | Description |\n");
-        md.push_str("|---------|------|--------|-------------|\n");
-        for cmd in &list {
-            let args = cmd.args.as_deref().unwrap_or("_none_");
-            let ret = cmd.ret.as_deref().unwrap_or("_none_");
-            let desc = if cmd.description.is_empty() {
-                ""
-            } else {
-                &cmd.description
+    // 3) For each service, render a page
+    let mut files = Vec::new();
+    let mut pages = Vec::new();
+    let mut all_services = Vec::new();
+    for (dir, cmds) in by_dir {
+        let dir_path: PathBuf = dir.iter().collect();
+        for (service, list) in group_by_service(cmds, &group_options) {
+            let (stem, title) = page_name(&service);
+            let mut md = String::new();
+            render_service(&mut md, &title, &list, 1, None, options);
+            let used = referenced_types(list.iter(), &structs, &enums);
+            render_reference(&mut md, &used, &structs, &enums, 1);
+
+            let filename = format!("{}.md", stem);
+            files.push((dir_path.join(&filename), md));
+            // Nav links are relative to `out_dir`, with `/` on every platform
+            let mut link = dir.clone();
+            link.push(filename);
+            pages.push((format!("{} Commands", title), link.join("/")));
+            if options.mermaid {
+                all_services.push((service, list));
+            }
+        }
+    }
+
+    // 4) Navigation file
+    if let Some(format) = options.nav {
+        pages.sort();
+        files.push(render_nav(format, &pages));
+    }
+
+    // 5) Overview graph
+    if options.mermaid {
+        let graph = mermaid_graph(&all_services, &structs, &enums);
+        files.push((PathBuf::from("commands.mmd"), graph));
+    }
+
+    Ok(files)
+}
+
+/// How many services (free commands counting as one) and commands
+/// [`generate_docs`] finds under `src_dirs`.
+pub fn count_commands(
+    src_dirs: &[impl AsRef<Path>],
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let (cmds, _, _) = collect(src_dirs)?;
+    let commands = cmds.len();
+    let services = group_by_service(cmds, &GenerateOptions::default()).len();
+    Ok((services, commands))
+}
+
+/// Like [`generate_docs`], but write every service into the single file `out_file`,
+/// with a table of contents up top and one shared struct reference at the bottom.
+///
+/// Command anchors are prefixed with their service (`#mycommands-greet`), so two
+/// services may have commands of the same name.
+pub fn generate_docs_combined(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    generate_docs_combined_with(src_dirs, out_file, &GenerateOptions::default())
+}
+
+/// Like [`generate_docs_combined`], with explicit [`GenerateOptions`].
+/// `nav` is ignored since there is only one page.
+pub fn generate_docs_combined_with(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+    options: &GenerateOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cmds, structs, enums) = collect(src_dirs)?;
+    let by_service = group_by_service(cmds, options);
+    let used = referenced_types(by_service.iter().flat_map(|(_, list)| list), &structs, &enums);
+
+    // table of contents
+    let mut md = String::from("# Commands\n\n");
+    for (service, _) in &by_service {
+        let (stem, title) = page_name(service);
+        md.push_str(&format!("- [{} Commands](#{})\n", title, stem));
+    }
+    if !used.is_empty() {
+        md.push_str("- [Struct Reference](#struct-reference)\n");
+    }
+    if options.mermaid {
+        let graph = mermaid_graph(&by_service, &structs, &enums);
+        md.push_str(&format!("\n```mermaid\n{}```\n", graph));
+    }
+
+    for (service, list) in &by_service {
+        let (stem, title) = page_name(service);
+        md.push_str(&format!("\n<a id=\"{}\"></a>\n\n", stem));
+        render_service(&mut md, &title, list, 2, Some(&stem), options);
+    }
+    render_reference(&mut md, &used, &structs, &enums, 2);
+
+    let out = out_file.as_ref();
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, md)?;
+    Ok(())
+}
+
+/// Group commands by the service they're documented under, sorted by title,
+/// with each service's commands sorted by name
+fn group_by_service(
+    cmds: Vec<CommandDoc>,
+    options: &GenerateOptions,
+) -> Vec<(String, Vec<CommandDoc>)> {
+    let mut by_service: HashMap<String, Vec<CommandDoc>> = HashMap::new();
+    for cmd in cmds {
+        let service = match cmd.module.last() {
+            Some(module) if options.group_free_by_module && cmd.service == "_free_" => {
+                module.clone()
+            }
+            _ => cmd.service.clone(),
+        };
+        by_service.entry(service).or_default().push(cmd);
+    }
+    let mut by_service: Vec<_> = by_service.into_iter().collect();
+    // Sort everything, down to same-named commands from different files, so
+    // regenerated docs only change when the sources do
+    for (_, list) in &mut by_service {
+        list.sort_by(|a, b| (&a.name, &a.file, a.line).cmp(&(&b.name, &b.file, b.line)));
+    }
+    by_service.sort_by(|(a, _), (b, _)| page_name(a).1.cmp(&page_name(b).1).then(a.cmp(b)));
+    by_service
+}
+
+/// File stem (also the section anchor when combined) and title for a service
+fn page_name(service: &str) -> (String, String) {
+    if service == "_free_" {
+        ("free_commands".to_string(), "Free Commands".to_string())
+    } else {
+        (service.to_lowercase(), service.to_string())
+    }
+}
+
+/// Anchors of the commands' detail sections, prefixed with their service when combined.
+/// Repeated slugs get `-1`, `-2`, ... appended, like GitHub and mdBook do for headings.
+fn command_anchors(prefix: Option<&str>, list: &[CommandDoc]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    list.iter()
+        .map(|cmd| {
+            let slug = match prefix {
+                Some(prefix) => slugify(&format!("{}-{}", prefix, cmd.name)),
+                None => slugify(&cmd.name),
             };
+            let count = seen.entry(slug.clone()).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => slug,
+                n => format!("{}-{}", slug, n - 1),
+            }
+        })
+        .collect()
+}
+
+/// Lowercase `text`, keeping alphanumerics, `-` and `_` and turning every other run of
+/// characters into a single `-`: `files/read` becomes `files-read`
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// A service's heading (at `level`), index table and one detail section per command
+fn render_service(
+    md: &mut String,
+    title: &str,
+    list: &[CommandDoc],
+    level: usize,
+    anchor_prefix: Option<&str>,
+    options: &GenerateOptions,
+) {
+    let heading = "#".repeat(level);
+    md.push_str(&format!("{} {} Commands\n\n", heading, title));
+
+    // index table, linking to explicit anchors since heading slugs differ per renderer
+    let anchors = command_anchors(anchor_prefix, list);
+    md.push_str("| Command | Args | Return | Description |\n");
+    md.push_str("|---------|------|--------|-------------|\n");
+    for (cmd, anchor) in list.iter().zip(&anchors) {
+        let args = cmd.args.as_deref().unwrap_or("_none_");
+        let ret = cmd.ret.as_deref().unwrap_or("_none_");
+        // only the first paragraph fits in a table cell
+        let mut desc = table_cell(cmd.description.split("\n\n").next().unwrap_or(""));
+        if cmd.deprecated.is_some() {
+            desc = format!("⚠️ **Deprecated.** {}", desc).trim_end().to_string();
+        }
+        md.push_str(&format!(
+            "| [{}](#{}) | `{}` | `{}` | {} |\n",
+            cmd.name,
+            anchor,
+            if args == "_none_" {
+                "()".to_string()
+            } else {
+                args.to_string()
+            },
+            if ret == "_none_" {
+                "()".to_string()
+            } else {
+                ret.to_string()
+            },
+            desc,
+        ));
+    }
+
+    // detail sections
+    for (cmd, anchor) in list.iter().zip(&anchors) {
+        md.push('\n');
+        md.push_str(&format!("<a id=\"{}\"></a>\n\n", anchor));
+        md.push_str(&format!("{}# {}\n\n", heading, cmd.name));
+        md.push_str(&format!(
+            "**Signature:** `fn {}({}) -> {}`\n\n",
+            cmd.name,
+            cmd.args.as_deref().filter(|a| *a != "_none_").unwrap_or(""),
+            cmd.ret.as_deref().unwrap_or("()"),
+        ));
+        if let Some(note) = &cmd.deprecated {
+            md.push_str(&format!("> ⚠️ **Deprecated:** {}\n\n", note));
+        }
+        if !cmd.description.is_empty() {
+            md.push_str("**Description:**  \n");
+            md.push_str(&cmd.description);
+            md.push_str("\n\n");
+        }
+        if !cmd.injected.is_empty() {
+            let injected: Vec<String> = cmd.injected.iter().map(|p| format!("`{}`", p)).collect();
             md.push_str(&format!(
-                "| [{}](#{}) | `{}` | `{}` | {} |\n",
-                cmd.name,
-                cmd.name.to_lowercase(),
-                if args == "_none_" {
-                    "()".to_string()
-                } else {
-                    args.to_string()
-                },
-                if ret == "_none_" {
-                    "()".to_string()
-                } else {
-                    ret.to_string()
-                },
-                desc,
+                "**Injected by the server:** {} (not sent by the caller)\n\n",
+                injected.join(", ")
             ));
         }
-
-        // detail sections
-        for cmd in &list {
-            md.push_str(&format!("\n## {}\n\n", cmd.name));
+        if let Some(item) = cmd.ret.as_deref().and_then(page_item) {
             md.push_str(&format!(
-                "**Signature:** `fn {}({}) -> {}`\n\n",
-                cmd.name,
-                cmd.args.as_deref().filter(|a| *a != "_none_").unwrap_or(""),
-                cmd.ret.as_deref().unwrap_or("()"),
+                "**Paginated:** returns a page of `{}` as `{{ items, total, page, per_page }}`\n\n",
+                item
             ));
-            if !cmd.description.is_empty() {
-                md.push_str("**Description:**  \n");
-                md.push_str(&cmd.description);
-                md.push_str("\n\n");
+        }
+        if cmd.ret.as_deref().map_or(false, |r| r.contains("WithWarnings")) {
+            md.push_str(
+                "**Warnings:** may succeed with warnings, returned as `{ value, warnings }` and in the `X-Warnings` header\n\n",
+            );
+        }
+        if cmd.detach {
+            md.push_str(
+                "**Detached:** responds `202 Accepted` with `{ job_id }` before the command completes\n\n",
+            );
+        }
+        if cmd.upgrade {
+            md.push_str(
+                "**Upgrade:** handles the raw request and sends its own response; the body is not JSON-decoded\n\n",
+            );
+        }
+        if !cmd.aliases.is_empty() {
+            md.push_str(&alias_notice(cmd));
+        }
+        md.push_str(&format!("**Source:** `{}:{}`\n\n", cmd.file, cmd.line));
+        if !cmd.required_headers.is_empty() {
+            let headers: Vec<String> = cmd
+                .required_headers
+                .iter()
+                .map(|h| format!("`{}`", h))
+                .collect();
+            md.push_str(&format!("**Required headers:** {}\n\n", headers.join(", ")));
+        }
+        if let Some(example) = &cmd.example {
+            let scheme = options.scheme.as_deref().unwrap_or("app");
+            md.push_str(&fetch_example(scheme, cmd, example));
+        }
+    }
+}
+
+/// The name a command is called by: `greet`, or `mycommands/greet` for a service's
+fn runtime_name(cmd: &CommandDoc) -> String {
+    if cmd.service == "_free_" {
+        cmd.name.clone()
+    } else {
+        format!("{}/{}", cmd.service.to_lowercase(), cmd.name)
+    }
+}
+
+/// A copy-pasteable `fetch` call sending `example` (pretty-printed when it's valid JSON)
+fn fetch_example(scheme: &str, cmd: &CommandDoc, example: &str) -> String {
+    let body = match serde_json::from_str::<serde_json::Value>(example) {
+        Ok(value) => serde_json::to_string_pretty(&value).unwrap_or_else(|_| example.into()),
+        Err(_) => example.to_string(),
+    };
+    let body = body.replace('\n', "\n  ");
+    format!(
+        "**Example:**\n\n```js\nconst res = await fetch(\"{}://{}\", {{\n  method: \"POST\",\n  \
+         headers: {{ \"Content-Type\": \"application/json\" }},\n  \
+         body: JSON.stringify({}),\n}});\nconst data = await res.json();\n```\n\n",
+        scheme,
+        runtime_name(cmd),
+        body
+    )
+}
+
+/// Structs and enums referenced by the commands' arguments and returns, and by their
+/// fields in turn, in first-use order
+fn referenced_types<'a>(
+    cmds: impl Iterator<Item = &'a CommandDoc>,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+) -> Vec<String> {
+    let mut pending: Vec<String> = cmds
+        .flat_map(|cmd| [&cmd.args, &cmd.ret])
+        .flatten()
+        .map(|t| page_item(t).unwrap_or_else(|| t.clone()))
+        .collect();
+    pending.reverse();
+
+    // Walk field types of every included type too; `visited` stops cycles
+    let mut used = Vec::new();
+    let mut visited = HashSet::new();
+    while let Some(ty) = pending.pop() {
+        for name in type_names(&ty) {
+            let Some(field_types) = field_types(&name, structs, enums) else {
+                continue;
+            };
+            if visited.insert(name.clone()) {
+                used.push(name);
+                pending.extend(field_types.into_iter().rev());
             }
         }
+    }
+    used
+}
 
-        // struct reference
-        let mut used = Vec::new();
-        for cmd in &list {
-            for ty in [&cmd.args, &cmd.ret] {
-                if let Some(t) = ty {
-                    let bare = t.split('<').next().unwrap().to_string();
-                    if structs.contains_key(&bare) && !used.contains(&bare) {
-                        used.push(bare);
+/// The field types of a documented struct, or of every variant of an enum
+fn field_types(
+    name: &str,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+) -> Option<Vec<String>> {
+    if let Some(sd) = structs.get(name) {
+        Some(sd.fields.iter().map(|f| f.ty.clone()).collect())
+    } else {
+        enums.get(name).map(|ed| {
+            ed.variants
+                .iter()
+                .flat_map(|v| match &v.kind {
+                    VariantKind::Unit => Vec::new(),
+                    VariantKind::Tuple(types) => types.clone(),
+                    VariantKind::Struct(fields) => {
+                        fields.iter().map(|(_, ty)| ty.clone()).collect()
                     }
+                })
+                .collect()
+        })
+    }
+}
+
+/// A Mermaid flowchart of `services`: service → command → the documented types
+/// its arguments and result name, and type → the documented types of its fields
+fn mermaid_graph(
+    services: &[(String, Vec<CommandDoc>)],
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+) -> String {
+    // Node ids are positional, so labels may hold any character but quotes
+    let label = |text: &str| text.replace('"', "#quot;");
+    let used = referenced_types(services.iter().flat_map(|(_, list)| list), structs, enums);
+    let type_id = |name: &str| used.iter().position(|u| u == name).map(|i| format!("t{}", i));
+    let known_types = |ty: &str| -> Vec<String> {
+        let mut ids: Vec<String> = type_names(ty).iter().filter_map(|n| type_id(n)).collect();
+        ids.dedup();
+        ids
+    };
+
+    let mut graph = String::from("graph LR\n");
+    for (i, (service, list)) in services.iter().enumerate() {
+        let (_, title) = page_name(service);
+        graph.push_str(&format!("    s{}[\"{}\"]\n", i, label(&title)));
+        for (j, cmd) in list.iter().enumerate() {
+            let cmd_id = format!("s{}c{}", i, j);
+            graph.push_str(&format!("    s{} --> {}(\"{}\")\n", i, cmd_id, label(&cmd.name)));
+            for ty in [&cmd.args, &cmd.ret].into_iter().flatten() {
+                for id in known_types(ty) {
+                    graph.push_str(&format!("    {} --> {}\n", cmd_id, id));
                 }
             }
         }
-        if !used.is_empty() {
-            md.push_str("\n# Struct Reference\n\n");
-            for name in used {
-                if let Some(sd) = structs.get(&name) {
-                    md.push_str(&format!("## `{}`\n\n", sd.name));
-                    if !sd.description.is_empty() {
-                        md.push_str(&format!("{}\n\n", sd.description));
-                    }
-                    md.push_str("| Field | Type | Description |\n");
-                    md.push_str("|-------|------|-------------|\n");
-                    for (fname, ftype, fdoc) in &sd.fields {
-                        md.push_str(&format!(
-                            "| `{}` | `{}` | {} |\n",
-                            fname,
-                            ftype,
-                            if fdoc.is_empty() { "" } else { fdoc }
-                        ));
-                    }
-                    md.push_str("\n");
-                }
+    }
+    for (i, name) in used.iter().enumerate() {
+        graph.push_str(&format!("    t{}{{{{\"{}\"}}}}\n", i, label(name)));
+        for ty in field_types(name, structs, enums).unwrap_or_default() {
+            for id in known_types(&ty) {
+                graph.push_str(&format!("    t{} -.-> {}\n", i, id));
             }
         }
+    }
+    graph
+}
+
+/// Every identifier in a type as written in source, outermost first:
+/// `Vec < Option < UserProfile > >` → `Vec`, `Option`, `UserProfile`.
+fn type_names(ty: &str) -> Vec<String> {
+    ty.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|part| part.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+        .map(str::to_string)
+        .collect()
+}
 
-        // write out
-        fs::write(out.join(filename), md)?;
+/// The struct reference section (heading at `level`) for the `used` types
+fn render_reference(
+    md: &mut String,
+    used: &[String],
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    level: usize,
+) {
+    if used.is_empty() {
+        return;
     }
+    let heading = "#".repeat(level);
+    md.push_str(&format!("\n{} Struct Reference\n\n", heading));
+    for name in used {
+        if let Some(sd) = structs.get(name) {
+            md.push_str(&format!("{}# `{}`\n\n", heading, sd.name));
+            if !sd.description.is_empty() {
+                md.push_str(&format!("{}\n\n", sd.description));
+            }
+            if sd.tuple && sd.fields.len() == 1 {
+                md.push_str("Sent as its inner value.\n\n");
+            } else if sd.tuple {
+                md.push_str("Sent as a JSON array, in field order.\n\n");
+            }
+            md.push_str("| Field | Type | Description |\n");
+            md.push_str("|-------|------|-------------|\n");
+            for field in &sd.fields {
+                let name = match &field.rust_name {
+                    Some(rust_name) => format!("`{}` (`{}`)", field.name, rust_name),
+                    None => format!("`{}`", field.name),
+                };
+                md.push_str(&format!(
+                    "| {} | `{}` | {} |\n",
+                    name,
+                    field.ty,
+                    table_cell(&field.doc)
+                ));
+            }
+            md.push_str("\n");
+        } else if let Some(ed) = enums.get(name) {
+            md.push_str(&enum_reference(ed, level + 1));
+        }
+    }
+}
 
-    Ok(())
+/// The navigation file listing `pages` as (title, filename) pairs
+fn render_nav(format: NavFormat, pages: &[(String, String)]) -> (PathBuf, String) {
+    match format {
+        NavFormat::MdBookSummary => {
+            let mut md = String::from("# Summary\n\n");
+            for (title, filename) in pages {
+                md.push_str(&format!("- [{}]({})\n", title, filename));
+            }
+            (PathBuf::from("SUMMARY.md"), md)
+        }
+    }
 }
 
 /// Walk items and collect all commands
 fn collect_commands(
     items: &[Item],
+    source: &Path,
+    module: &[String],
     out: &mut Vec<CommandDoc>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     for item in items {
@@ -192,7 +808,11 @@ fn collect_commands(
                 };
                 for inner in &imp.items {
                     if let ImplItem::Fn(m) = inner {
-                        let cmd = parse_method(m, &service)?.unwrap();
+                        // helpers marked #[command(skip)] aren't commands
+                        if command_attr_flag(&m.attrs, "skip") {
+                            continue;
+                        }
+                        let cmd = parse_method(m, &service, source, module)?.unwrap();
                         out.push(cmd);
                     }
                 }
@@ -200,33 +820,26 @@ fn collect_commands(
 
             // free fn #[command]
             Item::Fn(f) if f.attrs.iter().any(|a| a.path().is_ident("command")) => {
-                let cmd = parse_fn(f, "_free_")?.unwrap();
+                let cmd = parse_fn(f, "_free_", source, module)?.unwrap();
                 out.push(cmd);
             }
 
-            // fallback: individual methods #[command]
-            Item::Impl(imp) if imp.trait_.is_none() || imp.trait_.is_some() => {
-                let service = if let Some((_, path, _)) = &imp.trait_ {
-                    path.segments.last().unwrap().ident.to_string()
-                } else if let syn::Type::Path(tp) = &*imp.self_ty {
-                    tp.path.segments.last().unwrap().ident.to_string()
-                } else {
-                    "_".into()
-                };
-                for inner in &imp.items {
-                    if let ImplItem::Fn(m) = inner {
-                        if m.attrs.iter().any(|a| a.path().is_ident("command")) {
-                            let cmd = parse_method(m, &service)?.unwrap();
-                            out.push(cmd);
-                        }
-                    }
-                }
-            }
+            // `#[command]` on individual methods doesn't compile (the macro points to
+            // `#[commands]`), so impls without `#[commands]` register nothing
 
             // commands! macro invocation
             Item::Macro(mac) if mac.mac.path.is_ident("commands") => {
                 let nested: File = syn::parse2(mac.mac.tokens.clone())?;
-                collect_commands(&nested.items, out)?;
+                collect_commands(&nested.items, source, module, out)?;
+            }
+
+            // inline `mod name { ... }`
+            Item::Mod(m) => {
+                if let Some((_, nested)) = &m.content {
+                    let mut module = module.to_vec();
+                    module.push(m.ident.to_string());
+                    collect_commands(nested, source, &module, out)?;
+                }
             }
 
             _ => {}
@@ -250,15 +863,33 @@ fn collect_structs(
         {
             let name = ident.to_string();
             let description = collect_doc_comments(attrs);
+            let rename_all = attr_value(attrs, "serde", "rename_all");
             let mut field_docs = Vec::new();
 
-            // Iterate each field and only process those with an identifier
-            for field in fields.iter() {
-                if let Some(fident) = &field.ident {
-                    let fname = fident.to_string();
-                    let ftype = field.ty.to_token_stream().to_string();
-                    let fdoc = collect_doc_comments(&field.attrs);
-                    field_docs.push((fname, ftype, fdoc));
+            // Tuple struct fields are named by position, like serde does
+            let tuple = matches!(fields, Fields::Unnamed(_));
+            for (i, field) in fields.iter().enumerate() {
+                if tuple {
+                    field_docs.push(FieldDoc {
+                        name: i.to_string(),
+                        rust_name: None,
+                        ty: type_string(&field.ty),
+                        doc: collect_doc_comments(&field.attrs),
+                    });
+                } else if let Some(fident) = &field.ident {
+                    let rust_name = fident.to_string().trim_start_matches("r#").to_string();
+                    let wire_name = attr_value(&field.attrs, "serde", "rename").unwrap_or_else(|| {
+                        match rename_all.as_deref() {
+                            Some(rule) => rename_field(&rust_name, rule),
+                            None => rust_name.clone(),
+                        }
+                    });
+                    field_docs.push(FieldDoc {
+                        rust_name: (wire_name != rust_name).then_some(rust_name),
+                        name: wire_name,
+                        ty: type_string(&field.ty),
+                        doc: collect_doc_comments(&field.attrs),
+                    });
                 }
             }
 
@@ -268,25 +899,270 @@ fn collect_structs(
                     name,
                     description,
                     fields: field_docs,
+                    tuple,
                 },
             );
+        } else if let Item::Mod(m) = item {
+            if let Some((_, nested)) = &m.content {
+                collect_structs(nested, out)?;
+            }
         }
     }
     Ok(())
 }
 
+/// Walk items and collect all enums
+fn collect_enums(items: &[Item], out: &mut HashMap<String, EnumDoc>) {
+    for item in items {
+        if let Item::Enum(ItemEnum {
+            ident,
+            attrs,
+            variants,
+            ..
+        }) = item
+        {
+            let rename_all = attr_value(attrs, "serde", "rename_all");
+            let tag = attr_value(attrs, "serde", "tag");
+            let content = attr_value(attrs, "serde", "content");
+            let tagging = match (tag, content) {
+                _ if attr_flag(attrs, "serde", "untagged") => Tagging::Untagged,
+                (Some(tag), Some(content)) => Tagging::Adjacent(tag, content),
+                (Some(tag), None) => Tagging::Internal(tag),
+                (None, _) => Tagging::External,
+            };
+            let variants = variants
+                .iter()
+                .map(|v| {
+                    let kind = match &v.fields {
+                        Fields::Unit => VariantKind::Unit,
+                        Fields::Unnamed(fields) => VariantKind::Tuple(
+                            fields
+                                .unnamed
+                                .iter()
+                                .map(|f| type_string(&f.ty))
+                                .collect(),
+                        ),
+                        Fields::Named(fields) => VariantKind::Struct(
+                            fields
+                                .named
+                                .iter()
+                                .filter_map(|f| {
+                                    let fname = f.ident.as_ref()?.to_string();
+                                    Some((fname, type_string(&f.ty)))
+                                })
+                                .collect(),
+                        ),
+                    };
+                    let name = attr_value(&v.attrs, "serde", "rename").unwrap_or_else(|| {
+                        let name = v.ident.to_string();
+                        match rename_all.as_deref() {
+                            Some(rule) => rename_variant(&name, rule),
+                            None => name,
+                        }
+                    });
+                    VariantDoc {
+                        name,
+                        kind,
+                        description: collect_doc_comments(&v.attrs),
+                    }
+                })
+                .collect();
+
+            let name = ident.to_string();
+            out.insert(
+                name.clone(),
+                EnumDoc {
+                    name,
+                    description: collect_doc_comments(attrs),
+                    tagging,
+                    variants,
+                },
+            );
+        } else if let Item::Mod(m) = item {
+            if let Some((_, nested)) = &m.content {
+                collect_enums(nested, out);
+            }
+        }
+    }
+}
+
+/// The reference section for an enum (heading at `level`): its representation
+/// and a variant table
+fn enum_reference(ed: &EnumDoc, level: usize) -> String {
+    let mut md = format!("{} `{}`\n\n", "#".repeat(level), ed.name);
+    if !ed.description.is_empty() {
+        md.push_str(&format!("{}\n\n", ed.description));
+    }
+    md.push_str(&match &ed.tagging {
+        Tagging::External => "Externally tagged: the variant name is the key.\n\n".to_string(),
+        Tagging::Internal(tag) => format!("Internally tagged by `\"{}\"`.\n\n", tag),
+        Tagging::Adjacent(tag, content) => format!(
+            "Adjacently tagged by `\"{}\"`, with the payload under `\"{}\"`.\n\n",
+            tag, content
+        ),
+        Tagging::Untagged => "Untagged: only the payload is sent.\n\n".to_string(),
+    });
+    md.push_str("| Variant | Kind | JSON | Description |\n");
+    md.push_str("|---------|------|------|-------------|\n");
+    for v in &ed.variants {
+        let kind = match v.kind {
+            VariantKind::Unit => "unit",
+            VariantKind::Tuple(_) => "tuple",
+            VariantKind::Struct(_) => "struct",
+        };
+        md.push_str(&format!(
+            "| `{}` | {} | `{}` | {} |\n",
+            v.name,
+            kind,
+            variant_json(&ed.tagging, v),
+            table_cell(&v.description)
+        ));
+    }
+    md.push_str("\n");
+    md
+}
+
+/// Sketch of a variant's JSON under the enum's tagging, with types for values
+fn variant_json(tagging: &Tagging, v: &VariantDoc) -> String {
+    let fields = |fields: &[(String, String)]| -> Vec<String> {
+        fields
+            .iter()
+            .map(|(fname, ftype)| format!("\"{}\": {}", fname, ftype))
+            .collect()
+    };
+    let payload = match &v.kind {
+        VariantKind::Unit => None,
+        VariantKind::Tuple(types) if types.len() == 1 => Some(types[0].clone()),
+        VariantKind::Tuple(types) => Some(format!("[{}]", types.join(", "))),
+        VariantKind::Struct(named) => Some(format!("{{ {} }}", fields(named).join(", "))),
+    };
+    match (tagging, payload) {
+        (Tagging::External, None) => format!("\"{}\"", v.name),
+        (Tagging::External, Some(payload)) => format!("{{ \"{}\": {} }}", v.name, payload),
+        (Tagging::Internal(tag), None) => format!("{{ \"{}\": \"{}\" }}", tag, v.name),
+        (Tagging::Internal(tag), Some(payload)) => match &v.kind {
+            VariantKind::Struct(named) => {
+                format!("{{ \"{}\": \"{}\", {} }}", tag, v.name, fields(named).join(", "))
+            }
+            // serde flattens a newtype's fields next to the tag
+            _ => format!("{{ \"{}\": \"{}\", ...{} }}", tag, v.name, payload),
+        },
+        (Tagging::Adjacent(tag, _), None) => format!("{{ \"{}\": \"{}\" }}", tag, v.name),
+        (Tagging::Adjacent(tag, content), Some(payload)) => format!(
+            "{{ \"{}\": \"{}\", \"{}\": {} }}",
+            tag, v.name, content, payload
+        ),
+        (Tagging::Untagged, None) => "null".to_string(),
+        (Tagging::Untagged, Some(payload)) => payload,
+    }
+}
+
+/// A snake_case field name under a serde `rename_all` rule
+fn rename_field(name: &str, rule: &str) -> String {
+    let words: Vec<&str> = name.split('_').filter(|w| !w.is_empty()).collect();
+    apply_rename_rule(&words, rule).unwrap_or_else(|| name.to_string())
+}
+
+/// A PascalCase variant name under a serde `rename_all` rule
+fn rename_variant(name: &str, rule: &str) -> String {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in name.char_indices().skip(1) {
+        if c.is_uppercase() {
+            words.push(&name[start..i]);
+            start = i;
+        }
+    }
+    words.push(&name[start..]);
+    apply_rename_rule(&words, rule).unwrap_or_else(|| name.to_string())
+}
+
+/// Join `words` following one of serde's `rename_all` rules, or `None` for an unknown rule
+fn apply_rename_rule(words: &[&str], rule: &str) -> Option<String> {
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars.next().map_or(String::new(), |first| {
+            first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect()
+        })
+    };
+    let lower: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+    let upper: Vec<String> = words.iter().map(|w| w.to_uppercase()).collect();
+    Some(match rule {
+        "lowercase" => lower.concat(),
+        "UPPERCASE" => upper.concat(),
+        "PascalCase" => words.iter().map(|w| capitalize(w)).collect(),
+        "camelCase" => {
+            let mut out = lower.first().cloned().unwrap_or_default();
+            out.extend(words.iter().skip(1).map(|w| capitalize(w)));
+            out
+        }
+        "snake_case" => lower.join("_"),
+        "SCREAMING_SNAKE_CASE" => upper.join("_"),
+        "kebab-case" => lower.join("-"),
+        "SCREAMING-KEBAB-CASE" => upper.join("-"),
+        _ => return None,
+    })
+}
+
+/// The aliases line, with the deprecation timeline when one is declared
+fn alias_notice(cmd: &CommandDoc) -> String {
+    let aliases: Vec<String> = cmd.aliases.iter().map(|a| format!("`{}`", a)).collect();
+    let aliases = aliases.join(", ");
+    match (&cmd.alias_deprecated_since, &cmd.alias_removed_in) {
+        (None, None) => format!("**Aliases:** {}\n\n", aliases),
+        (since, removed) => {
+            let mut timeline = Vec::new();
+            if let Some(since) = since {
+                timeline.push(format!("deprecated since {}", since));
+            }
+            if let Some(removed) = removed {
+                timeline.push(format!("removed in {}", removed));
+            }
+            format!(
+                "**Deprecated aliases:** {} ({}); call `{}` instead\n\n",
+                aliases,
+                timeline.join(", "),
+                cmd.name
+            )
+        }
+    }
+}
+
 /// Parse a free function into a CommandDoc
-fn parse_fn(f: &ItemFn, service: &str) -> Result<Option<CommandDoc>, Box<dyn std::error::Error>> {
+fn parse_fn(
+    f: &ItemFn,
+    service: &str,
+    source: &Path,
+    module: &[String],
+) -> Result<Option<CommandDoc>, Box<dyn std::error::Error>> {
     let name = override_name(&f.attrs, f.sig.ident.to_string());
-    let args = first_arg(&f.sig.inputs);
-    let ret = first_return(&f.sig.output);
-    let description = collect_doc_comments(&f.attrs);
+    let upgrade = command_attr_flag(&f.attrs, "upgrade");
+    let context = command_attr_flag(&f.attrs, "context");
+    let (args, ret) = if upgrade {
+        (None, None)
+    } else {
+        (first_arg(&f.sig.inputs, context), first_return(&f.sig.output))
+    };
+    let description = command_description(&f.attrs, &f.sig.ident, source)?;
+    let required_headers = command_attr_values(&f.attrs, "require_header");
     Ok(Some(CommandDoc {
         service: service.into(),
         name,
         args,
         ret,
         description,
+        required_headers,
+        file: source.display().to_string(),
+        line: f.sig.ident.span().start().line,
+        module: module.to_vec(),
+        detach: command_attr_flag(&f.attrs, "detach"),
+        upgrade,
+        injected: injected_params(&f.sig.inputs, context),
+        aliases: command_attr_values(&f.attrs, "alias"),
+        alias_deprecated_since: command_attr_values(&f.attrs, "alias_deprecated_since").pop(),
+        alias_removed_in: command_attr_values(&f.attrs, "alias_removed_in").pop(),
+        deprecated: command_attr_values(&f.attrs, "deprecated").pop(),
+        example: command_attr_values(&f.attrs, "example").pop(),
     }))
 }
 
@@ -294,73 +1170,361 @@ fn parse_fn(f: &ItemFn, service: &str) -> Result<Option<CommandDoc>, Box<dyn std
 fn parse_method(
     m: &ImplItemFn,
     service: &str,
+    source: &Path,
+    module: &[String],
 ) -> Result<Option<CommandDoc>, Box<dyn std::error::Error>> {
     let name = override_name(&m.attrs, m.sig.ident.to_string());
-    let args = first_arg(&m.sig.inputs);
-    let ret = first_return(&m.sig.output);
-    let description = collect_doc_comments(&m.attrs);
+    let upgrade = command_attr_flag(&m.attrs, "upgrade");
+    let context = command_attr_flag(&m.attrs, "context");
+    let (args, ret) = if upgrade {
+        (None, None)
+    } else {
+        (first_arg(&m.sig.inputs, context), first_return(&m.sig.output))
+    };
+    let description = command_description(&m.attrs, &m.sig.ident, source)?;
+    let required_headers = command_attr_values(&m.attrs, "require_header");
     Ok(Some(CommandDoc {
         service: service.into(),
         name,
         args,
         ret,
         description,
+        required_headers,
+        file: source.display().to_string(),
+        line: m.sig.ident.span().start().line,
+        module: module.to_vec(),
+        detach: command_attr_flag(&m.attrs, "detach"),
+        upgrade,
+        injected: injected_params(&m.sig.inputs, context),
+        aliases: command_attr_values(&m.attrs, "alias"),
+        alias_deprecated_since: command_attr_values(&m.attrs, "alias_deprecated_since").pop(),
+        alias_removed_in: command_attr_values(&m.attrs, "alias_removed_in").pop(),
+        deprecated: command_attr_values(&m.attrs, "deprecated").pop(),
+        example: command_attr_values(&m.attrs, "example").pop(),
     }))
 }
 
+/// The command's description: the Markdown file named by `doc_file = "..."`
+/// (relative to the source file) if present, otherwise its `///` doc comments
+fn command_description(
+    attrs: &[Attribute],
+    ident: &Ident,
+    source: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let Some(doc_file) = command_attr_values(attrs, "doc_file").pop() else {
+        return Ok(collect_doc_comments(attrs));
+    };
+    let path = source.parent().unwrap_or(Path::new("")).join(&doc_file);
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(text.trim().to_string()),
+        Err(e) => Err(format!(
+            "{}: `#[command(doc_file = \"{}\")]` on `{}`: cannot read {}: {}",
+            source.display(),
+            doc_file,
+            ident,
+            path.display(),
+            e
+        )
+        .into()),
+    }
+}
+
 /// Look for `name = "..."` in #[command(...)]
 fn override_name(attrs: &[Attribute], default: String) -> String {
-    let mut name = default;
-    for a in attrs.iter().filter(|a| a.path().is_ident("command")) {
-        let nvs: Punctuated<MetaNameValue, Comma> = a
-            .parse_args_with(Punctuated::parse_terminated)
-            .unwrap_or_default();
-        for nv in nvs {
-            if nv.path.is_ident("name") {
+    command_attr_values(attrs, "name").pop().unwrap_or(default)
+}
+
+/// Collect every `key = "..."` value in #[command(...)], in order
+fn command_attr_values(attrs: &[Attribute], key: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    for meta in command_attr_metas(attrs) {
+        if let Meta::NameValue(nv) = meta {
+            if nv.path.is_ident(key) {
                 if let Expr::Lit(ExprLit {
                     lit: Lit::Str(s), ..
                 }) = nv.value
                 {
-                    name = s.value();
+                    values.push(s.value());
                 }
             }
         }
     }
-    name
+    values
 }
 
-/// Extract the first typed argument
-fn first_arg(inputs: &Punctuated<FnArg, Comma>) -> Option<String> {
-    for inp in inputs {
-        if let FnArg::Typed(pt) = inp {
-            return Some(pt.ty.to_token_stream().to_string());
-        }
+/// Whether a bare flag (e.g. `detach`) appears in #[command(...)]
+fn command_attr_flag(attrs: &[Attribute], key: &str) -> bool {
+    attr_flag(attrs, "command", key)
+}
+
+/// Every item inside every #[command(...)] attribute
+fn command_attr_metas(attrs: &[Attribute]) -> Vec<Meta> {
+    attr_metas(attrs, "command")
+}
+
+/// The first `key = "..."` value in #[attr(...)], e.g. `tag` in #[serde(tag = "type")]
+fn attr_value(attrs: &[Attribute], attr: &str, key: &str) -> Option<String> {
+    attr_metas(attrs, attr).into_iter().find_map(|meta| match meta {
+        Meta::NameValue(MetaNameValue {
+            path,
+            value: Expr::Lit(ExprLit {
+                lit: Lit::Str(s), ..
+            }),
+            ..
+        }) if path.is_ident(key) => Some(s.value()),
+        _ => None,
+    })
+}
+
+/// Whether a bare flag (e.g. `untagged`) appears in #[attr(...)]
+fn attr_flag(attrs: &[Attribute], attr: &str, key: &str) -> bool {
+    attr_metas(attrs, attr)
+        .into_iter()
+        .any(|meta| matches!(meta, Meta::Path(p) if p.is_ident(key)))
+}
+
+/// Every item inside every #[attr(...)] attribute
+fn attr_metas(attrs: &[Attribute], attr: &str) -> Vec<Meta> {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident(attr))
+        .flat_map(|a| {
+            a.parse_args_with(Punctuated::<Meta, Comma>::parse_terminated)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Parameter types `#[command]` fills from the request instead of the JSON body
+/// (kept in sync with `wry_cmd_macro`)
+const EXTRACTORS: &[&str] = &["PathTail", "State", "CommandContext"];
+
+/// Whether a parameter type is one of the [`EXTRACTORS`]
+fn is_extractor(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(tp) => tp
+            .path
+            .segments
+            .last()
+            .map_or(false, |seg| EXTRACTORS.iter().any(|e| seg.ident == e)),
+        _ => false,
+    }
+}
+
+/// Typed parameters, each paired with whether the server injects it: an
+/// extractor, or the first parameter of a `#[command(context)]`
+fn typed_params(inputs: &Punctuated<FnArg, Comma>, context: bool) -> Vec<(&PatType, bool)> {
+    inputs
+        .iter()
+        .filter_map(|inp| match inp {
+            FnArg::Typed(pt) => Some(pt),
+            _ => None,
+        })
+        .enumerate()
+        .map(|(i, pt)| (pt, is_extractor(&pt.ty) || (context && i == 0)))
+        .collect()
+}
+
+/// Extract the argument the frontend sends, skipping injected parameters: the
+/// single argument's type, or a tuple of them when the command takes several
+fn first_arg(inputs: &Punctuated<FnArg, Comma>, context: bool) -> Option<String> {
+    let args: Vec<String> = typed_params(inputs, context)
+        .into_iter()
+        .filter(|(_, injected)| !injected)
+        .map(|(pt, _)| type_string(&pt.ty))
+        .collect();
+    match args.len() {
+        0 => None,
+        1 => args.into_iter().next(),
+        _ => Some(format!("({})", args.join(", "))),
     }
-    None
+}
+
+/// Parameters injected by the server, as `pattern: Type`
+fn injected_params(inputs: &Punctuated<FnArg, Comma>, context: bool) -> Vec<String> {
+    typed_params(inputs, context)
+        .into_iter()
+        .filter(|(_, injected)| *injected)
+        .map(|(pt, _)| {
+            let pat = tidy_type(&pt.pat.to_token_stream().to_string());
+            format!("{}: {}", pat, type_string(&pt.ty))
+        })
+        .collect()
 }
 
 /// Extract the return type
 fn first_return(output: &ReturnType) -> Option<String> {
     if let ReturnType::Type(_, ty) = output {
-        Some(ty.to_token_stream().to_string())
+        Some(type_string(ty))
     } else {
         None
     }
 }
 
-/// Gather `///` doc comments
+/// A type as written in source: `HashMap<String, String>`
+fn type_string(ty: &syn::Type) -> String {
+    tidy_type(&ty.to_token_stream().to_string())
+}
+
+/// Remove the spaces `to_token_stream` puts between tokens, around `<`, `>` and `::`,
+/// before `,` and `;` and inside `&`, `(` and `)`: `Result < Vec < T > , String >`
+/// becomes `Result<Vec<T>, String>`. Spaces that matter (`dyn Trait`, `&mut T`) are kept.
+///
+/// `wry_cmd_macro` has its own copy for command metadata, pinned by the same tests.
+fn tidy_type(s: &str) -> String {
+    s.replace(" < ", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" ,", ",")
+        .replace(" ;", ";")
+        .replace("& ", "&")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
+
+/// If `ty` is `Page<T>` (optionally path-qualified), return `T`
+fn page_item(ty: &str) -> Option<String> {
+    let ty: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    let rest = ty.strip_prefix("Page<").or_else(|| {
+        ty.find("::Page<")
+            .filter(|i| !ty[..*i].contains('<'))
+            .map(|i| &ty[i + "::Page<".len()..])
+    })?;
+    rest.strip_suffix('>').map(|inner| inner.to_string())
+}
+
+/// Gather doc comments (`///`, `/** */` and `#[doc = "..."]`)
 fn collect_doc_comments(attrs: &[Attribute]) -> String {
     let mut lines = Vec::new();
     for attr in attrs.iter().filter(|a| a.path().is_ident("doc")) {
-        let joined = quote! { #attr };
-        if let Ok(MetaNameValue { value, .. }) = syn::parse2::<MetaNameValue>(joined) {
-            if let Expr::Lit(ExprLit {
+        // `///`, `/** */` and `#[doc = "..."]` all carry a string literal; anything
+        // else (`#[doc = include_str!(...)]`, `#[doc(hidden)]`) can't be read from
+        // source and is skipped without dropping the rest
+        if let Meta::NameValue(MetaNameValue {
+            value: Expr::Lit(ExprLit {
                 lit: Lit::Str(s), ..
-            }) = value
-            {
-                lines.push(s.value().trim().to_string());
+            }),
+            ..
+        }) = &attr.meta
+        {
+            // `/** */` comments arrive as one attribute spanning several lines. Split
+            // on '\n' rather than `lines()`, which yields nothing for an empty `///`:
+            // that blank line is what separates paragraphs
+            lines.extend(s.value().split('\n').map(str::to_string));
+        }
+    }
+    format_doc_lines(&lines)
+}
+
+/// Reflow doc comment lines as Markdown: lines of a paragraph are joined with a
+/// space, blank lines separate paragraphs and fenced code blocks are kept verbatim
+/// (minus the space after `///`).
+fn format_doc_lines(lines: &[String]) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut fence: Option<Vec<&str>> = None;
+    for line in lines {
+        let line = line.strip_prefix(' ').unwrap_or(line);
+        let is_fence = line.trim_start().starts_with("```");
+        if let Some(code) = &mut fence {
+            code.push(line.trim_end());
+            if is_fence {
+                blocks.push(code.join("\n"));
+                fence = None;
+            }
+        } else if is_fence {
+            if !paragraph.is_empty() {
+                blocks.push(paragraph.join(" "));
+                paragraph.clear();
+            }
+            fence = Some(vec![line.trim()]);
+        } else if line.trim().is_empty() {
+            if !paragraph.is_empty() {
+                blocks.push(paragraph.join(" "));
+                paragraph.clear();
             }
+        } else {
+            paragraph.push(line.trim());
         }
     }
-    lines.join(" ")
+    // An unclosed fence still ends the comment
+    if let Some(code) = fence {
+        blocks.push(code.join("\n"));
+    }
+    if !paragraph.is_empty() {
+        blocks.push(paragraph.join(" "));
+    }
+    blocks.join("\n\n")
+}
+
+/// Doc text on a single line for a Markdown table cell: paragraphs are separated
+/// by `<br>` and pipes escaped.
+fn table_cell(doc: &str) -> String {
+    doc.split("\n\n")
+        .map(|block| block.lines().map(str::trim).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("<br>")
+        .replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_strings_are_tidy() {
+        for (ty, expected) in [
+            ("Result<Vec<Option<T>>, String>", "Result<Vec<Option<T>>, String>"),
+            ("&'a mut T", "&'a mut T"),
+            ("&[u8]", "&[u8]"),
+            ("[u8; 4]", "[u8; 4]"),
+            ("dyn Trait", "dyn Trait"),
+            ("Box<dyn Trait>", "Box<dyn Trait>"),
+            ("(u32, String)", "(u32, String)"),
+            ("std::collections::HashMap<String, u32>", "std::collections::HashMap<String, u32>"),
+        ] {
+            let parsed: syn::Type = syn::parse_str(ty).unwrap();
+            assert_eq!(type_string(&parsed), expected);
+        }
+    }
+
+    /// Doc comments of a struct written as `source`
+    fn docs_of(source: &str) -> String {
+        let item: syn::ItemStruct = syn::parse_str(source).unwrap();
+        collect_doc_comments(&item.attrs)
+    }
+
+    #[test]
+    fn doc_comments_mix_attribute_forms() {
+        let docs = docs_of(
+            r#"
+            /// First line
+            /// continues here.
+            ///
+            #[doc = " Second paragraph,"]
+            #[doc = include_str!("../README.md")]
+            /// still second.
+            #[doc(hidden)]
+            ///
+            /// ```
+            /// let x = 1;
+            /// ```
+            struct S;
+            "#,
+        );
+        assert_eq!(
+            docs,
+            "First line continues here.\n\nSecond paragraph, still second.\n\n```\nlet x = 1;\n```"
+        );
+    }
+
+    #[test]
+    fn block_doc_comments_join_paragraphs() {
+        let docs = docs_of(
+            "/**\n Block comment\n over two lines.\n\n Next paragraph.\n*/\nstruct S;",
+        );
+        assert_eq!(docs, "Block comment over two lines.\n\nNext paragraph.");
+    }
 }
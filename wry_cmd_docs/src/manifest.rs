@@ -0,0 +1,66 @@
+//! A machine-readable manifest of the command surface, for tooling (CLIs, test
+//! harnesses) that needs to enumerate commands without parsing Markdown.
+
+use std::{fs, path::Path};
+
+use serde_json::{Value, json};
+
+use crate::{collect, runtime_name};
+
+/// Write a JSON array describing every command to `out_file`, sorted by name:
+///
+/// ```json
+/// [{
+///   "name": "mycommands/greet",
+///   "service": "MyCommands",
+///   "args": "GreetArgs",
+///   "returns": "GreetReply",
+///   "description": "Greets a user.",
+///   "location": { "file": "src/main.rs", "line": 12 }
+/// }]
+/// ```
+///
+/// `name` is the name the command is called by; `service` is `null` for free
+/// commands, and `args`/`returns` are `null` when the command takes or returns nothing.
+///
+/// ```rust,no_run
+/// wry_cmd_docs::generate_manifest(&["src"], "docs/commands.json")
+///     .expect("failed to generate command manifest");
+/// ```
+pub fn generate_manifest(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cmds, _, _) = collect(src_dirs)?;
+
+    let mut entries: Vec<(String, Value)> = cmds
+        .iter()
+        .map(|cmd| {
+            let name = runtime_name(cmd);
+            let service = (cmd.service != "_free_").then_some(&cmd.service);
+            let entry = json!({
+                "name": name,
+                "service": service,
+                "args": cmd.args,
+                "returns": cmd.ret,
+                "description": cmd.description,
+                "location": { "file": cmd.file, "line": cmd.line },
+                "detach": cmd.detach,
+                "upgrade": cmd.upgrade,
+                "aliases": cmd.aliases,
+                "deprecated": cmd.deprecated,
+                "required_headers": cmd.required_headers,
+            });
+            (name, entry)
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let manifest: Vec<Value> = entries.into_iter().map(|(_, entry)| entry).collect();
+
+    let out = out_file.as_ref();
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! OpenAPI 3.0 output for the command surface, for use with existing OpenAPI
+//! tooling (client generators, API explorers).
+//!
+//! Schemas come from the JSON Schema generator and are adjusted to OpenAPI's
+//! dialect: `$ref`s point into `components/schemas` and `null` unions become `nullable`.
+
+use std::{env, fs, path::Path};
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    collect, runtime_name,
+    schema::{enum_schema, struct_schema, type_schema},
+};
+
+/// Write an OpenAPI 3.0 document to `out_file` with one `POST /<command>` path per
+/// command: the argument type as the request body, the return type as the `200`
+/// response. Referenced structs and enums go under `components/schemas`.
+///
+/// The document's title and version are the crate's when run from a build script
+/// (`CARGO_PKG_NAME`/`CARGO_PKG_VERSION`). Upgrade commands are skipped since
+/// they don't speak JSON.
+///
+/// ```rust,no_run
+/// wry_cmd_docs::generate_openapi(&["src"], "docs/openapi.json")
+///     .expect("failed to generate OpenAPI spec");
+/// ```
+pub fn generate_openapi(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cmds, structs, enums) = collect(src_dirs)?;
+    cmds.sort_by(|a, b| (&a.service, &a.name).cmp(&(&b.service, &b.name)));
+
+    let error_schema = json!({
+        "type": "object",
+        "properties": { "error": { "type": "string" } },
+        "required": ["error"],
+    });
+
+    let mut used = Vec::new();
+    let mut paths = Map::new();
+    for cmd in cmds.iter().filter(|cmd| !cmd.upgrade) {
+        let key = runtime_name(cmd);
+
+        let mut operation = Map::new();
+        operation.insert("operationId".into(), key.replace('/', "_").into());
+        if let Some(summary) = cmd.description.lines().next() {
+            operation.insert("summary".into(), summary.into());
+        }
+        if !cmd.description.is_empty() {
+            operation.insert("description".into(), cmd.description.clone().into());
+        }
+        if cmd.service != "_free_" {
+            operation.insert("tags".into(), json!([cmd.service]));
+        }
+        if cmd.deprecated.is_some() {
+            operation.insert("deprecated".into(), true.into());
+        }
+        if let Some(args) = &cmd.args {
+            let schema = type_schema(args, &structs, &enums, &mut used);
+            operation.insert(
+                "requestBody".into(),
+                json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": schema } },
+                }),
+            );
+        }
+
+        let (status, description, schema) = if cmd.detach {
+            let job = json!({
+                "type": "object",
+                "properties": { "job_id": { "type": "string" } },
+                "required": ["job_id"],
+            });
+            ("202", "Accepted; the command runs in the background", job)
+        } else {
+            let schema = match &cmd.ret {
+                Some(ret) => type_schema(ret, &structs, &enums, &mut used),
+                None => json!({ "type": "null" }),
+            };
+            ("200", "Success", schema)
+        };
+        operation.insert(
+            "responses".into(),
+            json!({
+                status: {
+                    "description": description,
+                    "content": { "application/json": { "schema": schema } },
+                },
+                "default": {
+                    "description": "The command failed",
+                    "content": { "application/json": { "schema": error_schema } },
+                },
+            }),
+        );
+
+        paths.insert(format!("/{}", key), json!({ "post": operation }));
+    }
+
+    // Struct and enum schemas, including types only reachable through others
+    let mut schemas = Map::new();
+    let mut i = 0;
+    while i < used.len() {
+        let name = used[i].clone();
+        if let Some(sd) = structs.get(&name) {
+            let schema = struct_schema(sd, &structs, &enums, &mut used);
+            schemas.insert(name, schema);
+        } else if let Some(ed) = enums.get(&name) {
+            let schema = enum_schema(ed, &structs, &enums, &mut used);
+            schemas.insert(name, schema);
+        }
+        i += 1;
+    }
+
+    let mut doc = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env::var("CARGO_PKG_NAME").unwrap_or_else(|_| "Commands".to_string()),
+            "version": env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "0.0.0".to_string()),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas },
+    });
+    to_openapi_dialect(&mut doc);
+
+    let out = out_file.as_ref();
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, serde_json::to_string_pretty(&doc)?)?;
+    Ok(())
+}
+
+/// Rewrite draft-07 constructs OpenAPI 3.0 doesn't support, in place.
+fn to_openapi_dialect(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::String(r)) = map.get_mut("$ref") {
+                if let Some(name) = r.strip_prefix("#/definitions/") {
+                    *r = format!("#/components/schemas/{}", name);
+                }
+            }
+            // `{ "anyOf": [T, { "type": "null" }] }` → T, nullable
+            let nullable_of = match map.get("anyOf") {
+                Some(Value::Array(options)) if options.len() == 2 && is_null(&options[1]) => {
+                    Some(options[0].clone())
+                }
+                _ => None,
+            };
+            if let Some(inner) = nullable_of {
+                map.remove("anyOf");
+                match inner {
+                    Value::Object(inner) if !inner.contains_key("$ref") => map.extend(inner),
+                    other => {
+                        map.insert("allOf".into(), json!([other]));
+                    }
+                }
+                map.insert("nullable".into(), true.into());
+            }
+            if is_null_type(map) {
+                map.remove("type");
+                map.insert("nullable".into(), true.into());
+            }
+            // Positional arrays (several arguments) have no 3.0 equivalent
+            if let Some(Value::Array(items)) = map.get("items") {
+                let items = items.clone();
+                map.insert("items".into(), json!({ "oneOf": items }));
+            }
+            for child in map.values_mut() {
+                to_openapi_dialect(child);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(to_openapi_dialect),
+        _ => {}
+    }
+}
+
+fn is_null(value: &Value) -> bool {
+    value.as_object().map_or(false, is_null_type)
+}
+
+fn is_null_type(map: &Map<String, Value>) -> bool {
+    map.get("type").and_then(Value::as_str) == Some("null")
+}
@@ -0,0 +1,402 @@
+//! JSON Schema output for the command surface.
+//!
+//! Struct and field doc comments become `description`s, so the generated schema
+//! is self-documenting in tools like Swagger UI or form builders.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde_json::{Map, Value, json};
+
+use crate::{EnumDoc, StructDoc, Tagging, VariantDoc, VariantKind, collect, page_item};
+
+/// Write a JSON Schema (draft-07) document describing every command's arguments
+/// and return value to `out_file`, with referenced structs and enums under `definitions`.
+///
+/// ```rust,no_run
+/// wry_cmd_docs::generate_schema(&["src"], "docs/commands.schema.json")
+///     .expect("failed to generate command schema");
+/// ```
+pub fn generate_schema(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cmds, structs, enums) = collect(src_dirs)?;
+    cmds.sort_by(|a, b| (&a.service, &a.name).cmp(&(&b.service, &b.name)));
+
+    let mut used = Vec::new();
+    let mut commands = Map::new();
+    for cmd in &cmds {
+        let mut entry = Map::new();
+        if !cmd.description.is_empty() {
+            entry.insert("description".into(), cmd.description.clone().into());
+        }
+        if let Some(args) = &cmd.args {
+            entry.insert("args".into(), type_schema(args, &structs, &enums, &mut used));
+        }
+        if let Some(ret) = &cmd.ret {
+            entry.insert("returns".into(), type_schema(ret, &structs, &enums, &mut used));
+        }
+        let key = if cmd.service == "_free_" {
+            cmd.name.clone()
+        } else {
+            format!("{}/{}", cmd.service.to_lowercase(), cmd.name)
+        };
+        commands.insert(key, Value::Object(entry));
+    }
+
+    // Struct and enum definitions, including types only reachable through others
+    let mut definitions = Map::new();
+    let mut i = 0;
+    while i < used.len() {
+        let name = used[i].clone();
+        if let Some(sd) = structs.get(&name) {
+            let schema = struct_schema(sd, &structs, &enums, &mut used);
+            definitions.insert(name, schema);
+        } else if let Some(ed) = enums.get(&name) {
+            let schema = enum_schema(ed, &structs, &enums, &mut used);
+            definitions.insert(name, schema);
+        }
+        i += 1;
+    }
+
+    let doc = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "definitions": definitions,
+        "commands": commands,
+    });
+
+    let out = out_file.as_ref();
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, serde_json::to_string_pretty(&doc)?)?;
+    Ok(())
+}
+
+/// Schema for a struct: one property per named field, described by its doc comment.
+pub(crate) fn struct_schema(
+    sd: &StructDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    if sd.tuple {
+        return tuple_struct_schema(sd, structs, enums, used);
+    }
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for field in &sd.fields {
+        let mut schema = type_schema(&field.ty, structs, enums, used);
+        if !field.doc.is_empty() {
+            schema = with_description(schema, &field.doc);
+        }
+        if split_generic(&field.ty).0 != "Option" {
+            required.push(Value::from(field.name.clone()));
+        }
+        properties.insert(field.name.clone(), schema);
+    }
+
+    let mut schema = Map::new();
+    schema.insert("type".into(), "object".into());
+    if !sd.description.is_empty() {
+        schema.insert("description".into(), sd.description.clone().into());
+    }
+    schema.insert("properties".into(), Value::Object(properties));
+    if !required.is_empty() {
+        schema.insert("required".into(), Value::Array(required));
+    }
+    Value::Object(schema)
+}
+
+/// A newtype is its inner type's schema; a longer tuple struct a fixed-length array.
+fn tuple_struct_schema(
+    sd: &StructDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    let mut items: Vec<Value> = sd
+        .fields
+        .iter()
+        .map(|field| {
+            let schema = type_schema(&field.ty, structs, enums, used);
+            if field.doc.is_empty() {
+                schema
+            } else {
+                with_description(schema, &field.doc)
+            }
+        })
+        .collect();
+    let schema = if items.len() == 1 {
+        items.remove(0)
+    } else {
+        let len = items.len();
+        json!({ "type": "array", "items": items, "minItems": len, "maxItems": len })
+    };
+    if sd.description.is_empty() {
+        schema
+    } else {
+        with_description(schema, &sd.description)
+    }
+}
+
+/// Schema for a Rust type as written in source (token-stream spacing is fine).
+/// Named structs and enums become `$ref`s and are appended to `used`.
+pub(crate) fn type_schema(
+    ty: &str,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    if let Some(item) = page_item(ty) {
+        return json!({
+            "type": "object",
+            "description": "A page of results",
+            "properties": {
+                "items": { "type": "array", "items": type_schema(&item, structs, enums, used) },
+                "total": { "type": "integer", "minimum": 0 },
+                "page": { "type": "integer", "minimum": 0 },
+                "per_page": { "type": "integer", "minimum": 0 },
+            },
+            "required": ["items", "total", "page", "per_page"],
+        });
+    }
+
+    // Tuples (several command arguments) are positional arrays
+    let compact: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(inner) = compact.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        if !inner.is_empty() {
+            let items: Vec<Value> = split_top_level(inner)
+                .iter()
+                .map(|item| type_schema(item, structs, enums, used))
+                .collect();
+            let len = items.len();
+            return json!({
+                "type": "array",
+                "items": items,
+                "minItems": len,
+                "maxItems": len,
+            });
+        }
+    }
+
+    let (base, params) = split_generic(ty);
+    match (base.as_str(), params.as_slice()) {
+        ("String" | "str" | "char" | "PathBuf", _) => json!({ "type": "string" }),
+        ("bool", _) => json!({ "type": "boolean" }),
+        ("f32" | "f64", _) => json!({ "type": "number" }),
+        ("u8" | "u16" | "u32" | "u64" | "u128" | "usize", _) => {
+            json!({ "type": "integer", "minimum": 0 })
+        }
+        ("i8" | "i16" | "i32" | "i64" | "i128" | "isize", _) => json!({ "type": "integer" }),
+        ("()", _) => json!({ "type": "null" }),
+        ("Value", _) => json!({}),
+        ("Option", [inner]) => json!({
+            "anyOf": [type_schema(inner, structs, enums, used), { "type": "null" }]
+        }),
+        ("Vec" | "VecDeque" | "HashSet" | "BTreeSet", [inner]) => json!({
+            "type": "array",
+            "items": type_schema(inner, structs, enums, used),
+        }),
+        ("HashMap" | "BTreeMap", [_, value]) => json!({
+            "type": "object",
+            "additionalProperties": type_schema(value, structs, enums, used),
+        }),
+        ("Box" | "Arc" | "Rc", [inner]) => type_schema(inner, structs, enums, used),
+        ("WithWarnings", [inner]) => json!({
+            "type": "object",
+            "properties": {
+                "value": type_schema(inner, structs, enums, used),
+                "warnings": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["value", "warnings"],
+        }),
+        // commands unwrap `Result` returns: only `Ok` is sent as the response
+        ("Result", [ok, ..]) => type_schema(ok, structs, enums, used),
+        (name, _) if structs.contains_key(name) || enums.contains_key(name) => {
+            if !used.iter().any(|u| u == name) {
+                used.push(name.to_string());
+            }
+            json!({ "$ref": format!("#/definitions/{}", name) })
+        }
+        _ => json!({}),
+    }
+}
+
+/// Schema for an enum: `oneOf` its variants, each shaped by the enum's tagging like
+/// serde sends it, and described by its doc comment.
+pub(crate) fn enum_schema(
+    ed: &EnumDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    let variants: Vec<Value> = ed
+        .variants
+        .iter()
+        .map(|v| {
+            let schema = variant_schema(&ed.tagging, v, structs, enums, used);
+            if v.description.is_empty() {
+                schema
+            } else {
+                with_description(schema, &v.description)
+            }
+        })
+        .collect();
+    let schema = json!({ "oneOf": variants });
+    if ed.description.is_empty() {
+        schema
+    } else {
+        with_description(schema, &ed.description)
+    }
+}
+
+/// One variant under the enum's tagging.
+fn variant_schema(
+    tagging: &Tagging,
+    v: &VariantDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    let name = json!({ "type": "string", "enum": [v.name] });
+    let object = |properties: Map<String, Value>, required: Vec<String>| {
+        json!({ "type": "object", "properties": properties, "required": required })
+    };
+    match (tagging, &v.kind) {
+        (Tagging::External, VariantKind::Unit) => name,
+        (Tagging::External, _) => {
+            let payload = variant_payload(&v.kind, structs, enums, used);
+            let mut properties = Map::new();
+            properties.insert(v.name.clone(), payload);
+            object(properties, vec![v.name.clone()])
+        }
+        (Tagging::Internal(tag), VariantKind::Struct(fields)) => {
+            let mut properties = Map::new();
+            let mut required = vec![tag.clone()];
+            properties.insert(tag.clone(), name);
+            for (fname, ftype) in fields {
+                properties.insert(fname.clone(), type_schema(ftype, structs, enums, used));
+                if split_generic(ftype).0 != "Option" {
+                    required.push(fname.clone());
+                }
+            }
+            object(properties, required)
+        }
+        (Tagging::Internal(tag) | Tagging::Adjacent(tag, _), VariantKind::Unit) => {
+            let mut properties = Map::new();
+            properties.insert(tag.clone(), name);
+            object(properties, vec![tag.clone()])
+        }
+        // serde flattens a newtype's fields next to the tag
+        (Tagging::Internal(tag), _) => {
+            let mut properties = Map::new();
+            properties.insert(tag.clone(), name);
+            let payload = variant_payload(&v.kind, structs, enums, used);
+            json!({ "allOf": [object(properties, vec![tag.clone()]), payload] })
+        }
+        (Tagging::Adjacent(tag, content), _) => {
+            let mut properties = Map::new();
+            properties.insert(tag.clone(), name);
+            properties.insert(content.clone(), variant_payload(&v.kind, structs, enums, used));
+            object(properties, vec![tag.clone(), content.clone()])
+        }
+        (Tagging::Untagged, VariantKind::Unit) => json!({ "type": "null" }),
+        (Tagging::Untagged, _) => variant_payload(&v.kind, structs, enums, used),
+    }
+}
+
+/// A variant's payload: a newtype's inner value, a tuple's array or a struct's object.
+fn variant_payload(
+    kind: &VariantKind,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> Value {
+    match kind {
+        VariantKind::Unit => json!({ "type": "null" }),
+        VariantKind::Tuple(types) if types.len() == 1 => {
+            type_schema(&types[0], structs, enums, used)
+        }
+        VariantKind::Tuple(types) => {
+            let items: Vec<Value> =
+                types.iter().map(|ty| type_schema(ty, structs, enums, used)).collect();
+            let len = items.len();
+            json!({ "type": "array", "items": items, "minItems": len, "maxItems": len })
+        }
+        VariantKind::Struct(fields) => {
+            let mut properties = Map::new();
+            let mut required = Vec::new();
+            for (fname, ftype) in fields {
+                properties.insert(fname.clone(), type_schema(ftype, structs, enums, used));
+                if split_generic(ftype).0 != "Option" {
+                    required.push(Value::from(fname.clone()));
+                }
+            }
+            json!({ "type": "object", "properties": properties, "required": required })
+        }
+    }
+}
+
+/// Attach a description. `$ref` siblings are ignored by draft-07,
+/// so references are wrapped in `allOf` first.
+fn with_description(schema: Value, description: &str) -> Value {
+    match schema {
+        Value::Object(mut map) if !map.contains_key("$ref") => {
+            map.insert("description".into(), description.into());
+            Value::Object(map)
+        }
+        other => json!({ "allOf": [other], "description": description }),
+    }
+}
+
+/// Split `Vec < Option < u32 > >` into (`Vec`, [`Option<u32>`]), using the last
+/// path segment as the base name. Whitespace is removed; array/slice types
+/// (`[T]`, `[T; N]`) are reported as `Vec<T>`.
+pub(crate) fn split_generic(ty: &str) -> (String, Vec<String>) {
+    // Drop references (`& 'a mut T` → `T`) before whitespace is removed
+    let mut ty = ty.trim();
+    if let Some(rest) = ty.strip_prefix('&') {
+        ty = rest.trim_start();
+        if ty.starts_with('\'') {
+            ty = ty.split_once(char::is_whitespace).map_or("", |(_, t)| t.trim_start());
+        }
+        ty = ty.strip_prefix("mut ").unwrap_or(ty);
+    }
+    let ty: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    let ty = ty.as_str();
+
+    if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let elem = inner.split(';').next().unwrap_or(inner);
+        return ("Vec".to_string(), vec![elem.to_string()]);
+    }
+
+    let (path, params) = match ty.find('<') {
+        Some(i) if ty.ends_with('>') => (&ty[..i], &ty[i + 1..ty.len() - 1]),
+        _ => (ty, ""),
+    };
+    let base = path.rsplit("::").next().unwrap_or(path).to_string();
+    (base, split_top_level(params))
+}
+
+/// Split `a,B<c,d>,(e,f)` on the commas outside any brackets.
+pub(crate) fn split_top_level(params: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(params[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < params.len() {
+        args.push(params[start..].to_string());
+    }
+    args
+}
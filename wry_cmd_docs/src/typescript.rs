@@ -0,0 +1,348 @@
+//! TypeScript declarations for the command surface.
+//!
+//! Referenced structs become `interface`s, enums unions of their variants as serde
+//! sends them, and each command gets a typed
+//! `invoke<Name>` declaration, so frontends are checked against the Rust signatures.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    CommandDoc, EnumDoc, StructDoc, Tagging, VariantDoc, VariantKind, collect, page_item,
+    schema::{split_generic, split_top_level},
+};
+
+/// Write a `.d.ts` file to `out_file` with an `interface` per referenced struct
+/// and an `invoke<Name>(args)` declaration per command.
+///
+/// Commands of a `#[commands]` service are prefixed with the service name
+/// (`invokeMyCommandsGreet`); upgrade commands are skipped since they don't speak JSON.
+///
+/// ```rust,no_run
+/// wry_cmd_docs::generate_typescript(&["src"], "frontend/src/commands.d.ts")
+///     .expect("failed to generate TypeScript definitions");
+/// ```
+pub fn generate_typescript(
+    src_dirs: &[impl AsRef<Path>],
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut cmds, structs, enums) = collect(src_dirs)?;
+    cmds.sort_by(|a, b| (&a.service, &a.name).cmp(&(&b.service, &b.name)));
+
+    let mut used = Vec::new();
+    let mut functions = String::new();
+    for cmd in cmds.iter().filter(|cmd| !cmd.upgrade) {
+        functions.push_str(&function_decl(cmd, &structs, &enums, &mut used));
+    }
+
+    // Interfaces and enum unions, including types only reachable through others
+    let mut interfaces = String::new();
+    let mut i = 0;
+    while i < used.len() {
+        let name = used[i].clone();
+        match name.as_str() {
+            "Page" => interfaces.push_str(
+                "/** A page of results */\nexport interface Page<T> {\n  items: T[];\n  total: number;\n  page: number;\n  per_page: number;\n}\n\n",
+            ),
+            "WithWarnings" => interfaces.push_str(
+                "export interface WithWarnings<T> {\n  value: T;\n  warnings: string[];\n}\n\n",
+            ),
+            _ => {
+                if let Some(sd) = structs.get(&name) {
+                    interfaces.push_str(&interface_decl(sd, &structs, &enums, &mut used));
+                } else if let Some(ed) = enums.get(&name) {
+                    interfaces.push_str(&enum_decl(ed, &structs, &enums, &mut used));
+                }
+            }
+        }
+        i += 1;
+    }
+
+    let mut ts = String::from("// Generated by wry_cmd_docs. Do not edit.\n\n");
+    ts.push_str(&interfaces);
+    ts.push_str(&functions);
+
+    let out = out_file.as_ref();
+    if let Some(parent) = out.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out, ts)?;
+    Ok(())
+}
+
+/// `export declare function invokeName(args: Args): Promise<Ret>;` with its doc comment.
+fn function_decl(
+    cmd: &CommandDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    let prefix = if cmd.service == "_free_" {
+        String::new()
+    } else {
+        cmd.service.clone()
+    };
+    let name = format!("invoke{}{}", pascal_case(&prefix), pascal_case(&cmd.name));
+    let params = match &cmd.args {
+        Some(args) => format!("args: {}", ts_type(args, structs, enums, used)),
+        None => String::new(),
+    };
+    let ret = if cmd.detach {
+        "{ job_id: string }".to_string()
+    } else {
+        cmd.ret
+            .as_deref()
+            .map_or("null".to_string(), |ret| ts_type(ret, structs, enums, used))
+    };
+    // `@deprecated` makes editors strike the function through
+    let doc = match &cmd.deprecated {
+        Some(note) if cmd.description.is_empty() => format!("@deprecated {}", note),
+        Some(note) => format!("{}\n\n@deprecated {}", cmd.description, note),
+        None => cmd.description.clone(),
+    };
+    format!(
+        "{}export declare function {}({}): Promise<{}>;\n\n",
+        doc_comment(&doc, ""),
+        name,
+        params,
+        ret
+    )
+}
+
+/// `export interface Name { field: type; ... }` with struct and field doc comments,
+/// or `export type Name = ...` for tuple structs.
+fn interface_decl(
+    sd: &StructDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    let mut ts = doc_comment(&sd.description, "");
+    if sd.tuple {
+        // Newtypes are sent as their inner value, longer tuple structs as arrays
+        let items: Vec<String> =
+            sd.fields.iter().map(|field| ts_type(&field.ty, structs, enums, used)).collect();
+        let ty = match items.as_slice() {
+            [inner] => inner.clone(),
+            _ => format!("[{}]", items.join(", ")),
+        };
+        ts.push_str(&format!("export type {} = {};\n\n", sd.name, ty));
+        return ts;
+    }
+    ts.push_str(&format!("export interface {} {{\n", sd.name));
+    for field in &sd.fields {
+        ts.push_str(&doc_comment(&field.doc, "  "));
+        let ty = ts_type(&field.ty, structs, enums, used);
+        ts.push_str(&format!("  {}: {};\n", property_name(&field.name), ty));
+    }
+    ts.push_str("}\n\n");
+    ts
+}
+
+/// `export type Name = | A | B;`, one member per variant under the enum's tagging,
+/// with enum and variant doc comments.
+fn enum_decl(
+    ed: &EnumDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    let mut ts = doc_comment(&ed.description, "");
+    ts.push_str(&format!("export type {} =
+", ed.name));
+    if ed.variants.is_empty() {
+        ts.push_str("  never");
+    }
+    for v in &ed.variants {
+        ts.push_str(&doc_comment(&v.description, "  "));
+        ts.push_str(&format!("  | {}
+", variant_type(&ed.tagging, v, structs, enums, used)));
+    }
+    ts = ts.trim_end().to_string();
+    ts.push_str(";
+
+");
+    ts
+}
+
+/// One variant under the enum's tagging.
+fn variant_type(
+    tagging: &Tagging,
+    v: &VariantDoc,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    let name = format!("\"{}\"", v.name);
+    match (tagging, &v.kind) {
+        (Tagging::External, VariantKind::Unit) => name,
+        (Tagging::External, kind) => format!(
+            "{{ {}: {} }}",
+            property_name(&v.name),
+            variant_payload(kind, structs, enums, used)
+        ),
+        (Tagging::Internal(tag) | Tagging::Adjacent(tag, _), VariantKind::Unit) => {
+            format!("{{ {}: {} }}", property_name(tag), name)
+        }
+        (Tagging::Internal(tag), VariantKind::Struct(fields)) => {
+            let mut members = vec![format!("{}: {}", property_name(tag), name)];
+            for (fname, ftype) in fields {
+                let ty = ts_type(ftype, structs, enums, used);
+                members.push(format!("{}: {}", property_name(fname), ty));
+            }
+            format!("{{ {} }}", members.join("; "))
+        }
+        // serde flattens a newtype's fields next to the tag
+        (Tagging::Internal(tag), kind) => format!(
+            "({{ {}: {} }} & {})",
+            property_name(tag),
+            name,
+            variant_payload(kind, structs, enums, used)
+        ),
+        (Tagging::Adjacent(tag, content), kind) => format!(
+            "{{ {}: {}; {}: {} }}",
+            property_name(tag),
+            name,
+            property_name(content),
+            variant_payload(kind, structs, enums, used)
+        ),
+        (Tagging::Untagged, VariantKind::Unit) => "null".to_string(),
+        (Tagging::Untagged, kind) => variant_payload(kind, structs, enums, used),
+    }
+}
+
+/// A variant's payload: a newtype's inner value, a tuple's array or a struct's object.
+fn variant_payload(
+    kind: &VariantKind,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    match kind {
+        VariantKind::Unit => "null".to_string(),
+        VariantKind::Tuple(types) if types.len() == 1 => ts_type(&types[0], structs, enums, used),
+        VariantKind::Tuple(types) => {
+            let items: Vec<String> =
+                types.iter().map(|ty| ts_type(ty, structs, enums, used)).collect();
+            format!("[{}]", items.join(", "))
+        }
+        VariantKind::Struct(fields) => {
+            let members: Vec<String> = fields
+                .iter()
+                .map(|(fname, ftype)| {
+                    format!("{}: {}", property_name(fname), ts_type(ftype, structs, enums, used))
+                })
+                .collect();
+            format!("{{ {} }}", members.join("; "))
+        }
+    }
+}
+
+/// A property name, quoted unless it's a plain identifier.
+fn property_name(name: &str) -> String {
+    if name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$') {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+/// TypeScript for a Rust type as written in source (token-stream spacing is fine).
+/// Named structs and enums are referenced by name and appended to `used`.
+fn ts_type(
+    ty: &str,
+    structs: &HashMap<String, StructDoc>,
+    enums: &HashMap<String, EnumDoc>,
+    used: &mut Vec<String>,
+) -> String {
+    if let Some(item) = page_item(ty) {
+        mark_used(used, "Page");
+        return format!("Page<{}>", ts_type(&item, structs, enums, used));
+    }
+
+    // Tuples (several command arguments) are positional arrays
+    let compact: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(inner) = compact.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        if !inner.is_empty() {
+            let items: Vec<String> = split_top_level(inner)
+                .iter()
+                .map(|item| ts_type(item, structs, enums, used))
+                .collect();
+            return format!("[{}]", items.join(", "));
+        }
+    }
+
+    let (base, params) = split_generic(ty);
+    match (base.as_str(), params.as_slice()) {
+        ("String" | "str" | "char" | "PathBuf", _) => "string".to_string(),
+        ("bool", _) => "boolean".to_string(),
+        (
+            "f32" | "f64" | "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32"
+            | "i64" | "i128" | "isize",
+            _,
+        ) => "number".to_string(),
+        ("()", _) => "null".to_string(),
+        ("Value", _) => "unknown".to_string(),
+        ("Option", [inner]) => format!("{} | null", ts_type(inner, structs, enums, used)),
+        ("Vec" | "VecDeque" | "HashSet" | "BTreeSet", [inner]) => {
+            let item = ts_type(inner, structs, enums, used);
+            if item.contains(' ') {
+                format!("({})[]", item)
+            } else {
+                format!("{}[]", item)
+            }
+        }
+        ("HashMap" | "BTreeMap", [_, value]) => {
+            format!("Record<string, {}>", ts_type(value, structs, enums, used))
+        }
+        ("Box" | "Arc" | "Rc", [inner]) => ts_type(inner, structs, enums, used),
+        ("WithWarnings", [inner]) => {
+            mark_used(used, "WithWarnings");
+            format!("WithWarnings<{}>", ts_type(inner, structs, enums, used))
+        }
+        // commands unwrap `Result` returns: only `Ok` is sent as the response
+        ("Result", [ok, ..]) => ts_type(ok, structs, enums, used),
+        (name, _) if structs.contains_key(name) || enums.contains_key(name) => {
+            mark_used(used, name);
+            name.to_string()
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Record that `name` needs a declaration.
+fn mark_used(used: &mut Vec<String>, name: &str) {
+    if !used.iter().any(|u| u == name) {
+        used.push(name.to_string());
+    }
+}
+
+/// `/** ... */` over one or more lines, or nothing for an empty doc.
+fn doc_comment(doc: &str, indent: &str) -> String {
+    let lines: Vec<&str> = doc.lines().collect();
+    match lines.as_slice() {
+        [] => String::new(),
+        [line] => format!("{}/** {} */\n", indent, line),
+        lines => {
+            let mut out = format!("{}/**\n", indent);
+            for line in lines {
+                out.push_str(&format!("{} * {}\n", indent, line).replace(" * \n", " *\n"));
+            }
+            out.push_str(&format!("{} */\n", indent));
+            out
+        }
+    }
+}
+
+/// `get_user` / `MyCommands` → `GetUser` / `MyCommands`
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| c == '_' || c == '-' || c == '/')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
@@ -0,0 +1,84 @@
+//! Regenerate docs whenever the sources change, for a standalone docs binary
+//! (`cargo run --bin docsgen`) rather than a build script.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use notify::{EventKind, RecursiveMode, Watcher, event::ModifyKind};
+
+use crate::generate_docs;
+
+/// How long the sources must stay quiet before docs are regenerated, so a
+/// save touching several files (or an editor writing a temp file first)
+/// triggers one run.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Run [`generate_docs`] now and again whenever a `.rs` file under `src_dirs`
+/// changes, printing a line per run. Blocks until the watcher stops.
+///
+/// Generation errors are printed and watching continues; only failing to set
+/// up the watcher is returned.
+///
+/// ```rust,no_run
+/// // src/bin/docsgen.rs
+/// fn main() {
+///     wry_cmd_docs::generate_docs_watch(&["src"], "docs/commands")
+///         .expect("failed to watch sources");
+/// }
+/// ```
+pub fn generate_docs_watch(
+    src_dirs: &[impl AsRef<Path>],
+    out_dir: impl AsRef<Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let src_dirs: Vec<PathBuf> = src_dirs.iter().map(|d| d.as_ref().to_path_buf()).collect();
+    let out_dir = out_dir.as_ref();
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for dir in &src_dirs {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+    }
+
+    regenerate(&src_dirs, out_dir);
+    while let Ok(event) = rx.recv() {
+        if !touches_source(&event) {
+            continue;
+        }
+        // Wait for the burst of events to settle
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        regenerate(&src_dirs, out_dir);
+    }
+    Ok(())
+}
+
+fn regenerate(src_dirs: &[PathBuf], out_dir: &Path) {
+    match generate_docs(src_dirs, out_dir) {
+        Ok(()) => println!("regenerated command docs in {}", out_dir.display()),
+        Err(e) => eprintln!("failed to regenerate command docs: {}", e),
+    }
+}
+
+/// A change to a `.rs` file (reads and metadata-only events don't count).
+fn touches_source(event: &notify::Result<notify::Event>) -> bool {
+    match event {
+        Ok(event) => {
+            !matches!(
+                event.kind,
+                EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))
+            ) && event
+                .paths
+                .iter()
+                .any(|p| p.extension().is_some_and(|ext| ext == "rs"))
+        }
+        Err(_) => false,
+    }
+}
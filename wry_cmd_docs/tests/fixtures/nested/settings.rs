@@ -0,0 +1,25 @@
+use wry_cmd::command;
+
+/// Window placement
+pub struct Window {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+}
+
+/// Everything the settings page edits
+pub struct Settings {
+    /// Shown in the title bar
+    pub title: String,
+    /// Where the main window opens
+    pub window: Window,
+    /// Last placement, if the window was ever moved
+    pub last_window: Option<Window>,
+}
+
+/// Save the settings and return them as stored
+#[command]
+pub fn save_settings(settings: Settings) -> Settings {
+    settings
+}
@@ -0,0 +1,44 @@
+use serde_json::{Value, json};
+use std::{fs, path::PathBuf};
+
+/// Generate the schema for a fixture under `tests/fixtures` and read it back.
+fn schema_for(fixture: &str) -> Value {
+    let src = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+    let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.schema.json", fixture));
+    wry_cmd_docs::generate_schema(&[src], &out).expect("failed to generate schema");
+    serde_json::from_str(&fs::read_to_string(out).unwrap()).unwrap()
+}
+
+#[test]
+fn nested_struct_field_docs_become_descriptions() {
+    let schema = schema_for("nested");
+    let settings = &schema["definitions"]["Settings"];
+    assert_eq!(settings["description"], "Everything the settings page edits");
+    assert_eq!(
+        settings["properties"]["title"],
+        json!({ "type": "string", "description": "Shown in the title bar" })
+    );
+    // A `$ref` can't carry siblings in draft-07, so it's wrapped in `allOf`
+    assert_eq!(
+        settings["properties"]["window"],
+        json!({
+            "allOf": [{ "$ref": "#/definitions/Window" }],
+            "description": "Where the main window opens",
+        })
+    );
+    assert_eq!(
+        settings["properties"]["last_window"]["description"],
+        "Last placement, if the window was ever moved"
+    );
+    assert_eq!(settings["required"], json!(["title", "window"]));
+
+    // Reached only through `Settings`'s fields
+    let window = &schema["definitions"]["Window"];
+    assert_eq!(window["description"], "Window placement");
+    assert_eq!(window["properties"]["width"]["description"], "Width in pixels");
+    assert_eq!(window["properties"]["height"]["description"], "Height in pixels");
+
+    let command = &schema["commands"]["save_settings"];
+    assert_eq!(command["description"], "Save the settings and return them as stored");
+    assert_eq!(command["args"], json!({ "$ref": "#/definitions/Settings" }));
+}
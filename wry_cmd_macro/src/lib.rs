@@ -5,50 +5,677 @@ extern crate inflector;
 extern crate proc_macro;
 use inflector::Inflector;
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, quote_spanned};
 use syn::{
     parse_macro_input, parse_quote, spanned::Spanned, AttributeArgs, FnArg, ImplItem, ItemFn,
-    ItemImpl, Lit, LitStr, Meta, NestedMeta, PatType, ReturnType, Type,
+    ItemImpl, Lit, LitInt, LitStr, Meta, MetaNameValue, NestedMeta, PatType, Path, ReturnType,
+    Type,
 };
 
-/// Marks a function as a Wry IPC command.
-/// The function can take zero or one argument implementing `Deserialize`
-/// and return a type implementing `Serialize`. If omitted, no args or no return are supported.
-/// Use `#[command(name = "...")]` or just `#[command]`.
-#[proc_macro_attribute]
-pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // Parse optional `name = "..."` from attribute
-    let args = parse_macro_input!(attr as AttributeArgs);
-    let mut override_name: Option<LitStr> = None;
-    for nested in args {
-        if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
-            if nv.path.is_ident("name") {
-                if let Lit::Str(ls) = nv.lit {
-                    override_name = Some(ls);
+/// Options accepted by `#[command(...)]`.
+#[derive(Default)]
+struct CommandArgs {
+    /// `name = "..."`: overrides the registered command name.
+    name: Option<LitStr>,
+    /// `require_header = "..."`: may be repeated once per required header.
+    required_headers: Vec<LitStr>,
+    /// `cache_ttl_ms = ...`: memoize successful results for this long.
+    cache_ttl_ms: Option<LitInt>,
+    /// `timeout_ms = ...`: fail the command if it runs longer than this.
+    timeout_ms: Option<LitInt>,
+    /// `max_concurrent = ...`: run at most this many calls at once.
+    max_concurrent: Option<LitInt>,
+    /// `reject_when_busy`: fail calls past `max_concurrent` instead of queueing them.
+    reject_when_busy: Option<Path>,
+    /// `error_status = 400`: HTTP status sent when the command fails.
+    error_status: Option<LitInt>,
+    /// `deserialize_with = "path"`: custom `fn(Value) -> Result<Arg, String>`.
+    deserialize_with: Option<Path>,
+    /// `detach`: answer `202 Accepted` immediately and run in the background.
+    detach: bool,
+    /// `method = "GET"`: also accept GET, with args from the query string.
+    method: Option<LitStr>,
+    /// `upgrade`: the function takes the raw request and responder itself.
+    upgrade: bool,
+    /// `stream` or `stream = "content/type"`: the command returns a byte stream.
+    stream: Option<LitStr>,
+    /// `no_smoke_test`: leave the command out of `wry_cmd::smoke_test!()`.
+    no_smoke_test: bool,
+    /// `context`: the first parameter receives the `CommandContext`.
+    context: bool,
+    /// `alias = "..."`: may be repeated once per alternate name.
+    aliases: Vec<LitStr>,
+    /// `alias_deprecated_since = "..."`: version the aliases were deprecated in.
+    alias_deprecated_since: Option<LitStr>,
+    /// `alias_removed_in = "..."`: version the aliases are removed in.
+    alias_removed_in: Option<LitStr>,
+    /// `deprecated = "..."`: why the command is deprecated, logged on every call.
+    deprecated: Option<LitStr>,
+    /// `error = "json"`: serialize `Err` values as JSON instead of `to_string()`.
+    json_error: Option<LitStr>,
+    /// `bigint_as_string`: send integers beyond JS's safe range as strings.
+    bigint_as_string: Option<Path>,
+}
+
+impl CommandArgs {
+    fn parse(args: AttributeArgs) -> syn::Result<Self> {
+        let mut out = CommandArgs::default();
+        for nested in args {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) => {
+                    if path.is_ident("detach") {
+                        out.detach = true;
+                    } else if path.is_ident("upgrade") {
+                        out.upgrade = true;
+                    } else if path.is_ident("no_smoke_test") {
+                        out.no_smoke_test = true;
+                    } else if path.is_ident("context") {
+                        out.context = true;
+                    } else if path.is_ident("reject_when_busy") {
+                        out.reject_when_busy = Some(path);
+                    } else if path.is_ident("bigint_as_string") {
+                        out.bigint_as_string = Some(path);
+                    } else if path.is_ident("stream") {
+                        out.stream = Some(LitStr::new("application/octet-stream", path.span()));
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) => {
+                    if nv.path.is_ident("name") {
+                        out.name = Some(command_name(&nv)?);
+                    } else if nv.path.is_ident("require_header") {
+                        let header = lit_str(&nv)?;
+                        if header.value().parse::<http::HeaderName>().is_err() {
+                            return Err(syn::Error::new(
+                                header.span(),
+                                format!("`{}` isn't a valid header name", header.value()),
+                            ));
+                        }
+                        out.required_headers.push(header);
+                    } else if nv.path.is_ident("cache_ttl_ms") {
+                        let ttl = lit_int(&nv)?;
+                        ttl.base10_parse::<u64>()?;
+                        out.cache_ttl_ms = Some(ttl);
+                    } else if nv.path.is_ident("timeout_ms") {
+                        let timeout = lit_int(&nv)?;
+                        timeout.base10_parse::<u64>()?;
+                        out.timeout_ms = Some(timeout);
+                    } else if nv.path.is_ident("max_concurrent") {
+                        let max = lit_int(&nv)?;
+                        if max.base10_parse::<usize>()? == 0 {
+                            return Err(syn::Error::new(
+                                max.span(),
+                                "`max_concurrent` must be at least 1",
+                            ));
+                        }
+                        out.max_concurrent = Some(max);
+                    } else if nv.path.is_ident("error_status") {
+                        let status = lit_int(&nv)?;
+                        if !(400..=599).contains(&status.base10_parse::<u16>()?) {
+                            return Err(syn::Error::new(
+                                status.span(),
+                                "`error_status` must be a 4xx or 5xx status",
+                            ));
+                        }
+                        out.error_status = Some(status);
+                    } else if nv.path.is_ident("error") {
+                        let error = lit_str(&nv)?;
+                        match error.value().as_str() {
+                            "json" => out.json_error = Some(error),
+                            "string" => out.json_error = None,
+                            _ => {
+                                return Err(syn::Error::new(
+                                    error.span(),
+                                    "`error` must be \"json\" or \"string\"",
+                                ))
+                            }
+                        }
+                    } else if nv.path.is_ident("deserialize_with") {
+                        out.deserialize_with = Some(lit_str(&nv)?.parse()?);
+                    } else if nv.path.is_ident("method") {
+                        let method = lit_str(&nv)?;
+                        let upper = method.value().to_ascii_uppercase();
+                        if upper != "GET" && upper != "POST" {
+                            return Err(syn::Error::new(
+                                method.span(),
+                                "`method` must be \"GET\" or \"POST\"",
+                            ));
+                        }
+                        out.method = Some(LitStr::new(&upper, method.span()));
+                    } else if nv.path.is_ident("stream") {
+                        out.stream = Some(lit_str(&nv)?);
+                    } else if nv.path.is_ident("alias") {
+                        out.aliases.push(command_name(&nv)?);
+                    } else if nv.path.is_ident("alias_deprecated_since") {
+                        out.alias_deprecated_since = Some(lit_str(&nv)?);
+                    } else if nv.path.is_ident("alias_removed_in") {
+                        out.alias_removed_in = Some(lit_str(&nv)?);
+                    } else if nv.path.is_ident("deprecated") {
+                        out.deprecated = Some(lit_str(&nv)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Extract a string literal from `key = "..."`, or error at the literal.
+fn lit_str(nv: &MetaNameValue) -> syn::Result<LitStr> {
+    match &nv.lit {
+        Lit::Str(ls) => Ok(ls.clone()),
+        other => Err(syn::Error::new(
+            other.span(),
+            format!(
+                "`{}` must be a string literal",
+                nv.path.get_ident().map(|i| i.to_string()).unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+/// Extract a command name from `key = "..."`, checking it can be reached through a
+/// URL: ASCII alphanumerics, `-` and `_`, in segments separated by single `/`s or `.`s.
+fn command_name(nv: &MetaNameValue) -> syn::Result<LitStr> {
+    let lit = lit_str(nv)?;
+    let name = lit.value();
+    let valid_segment = |segment: &str| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if !name.split(['/', '.']).all(valid_segment) {
+        return Err(syn::Error::new(
+            lit.span(),
+            format!(
+                "invalid command name {:?}: use ASCII letters, digits, `-` and `_`, \
+                 with `/` or `.` only between non-empty segments",
+                name
+            ),
+        ));
+    }
+    Ok(lit)
+}
+
+/// Extract an integer literal from `key = 123`, or error at the literal.
+fn lit_int(nv: &MetaNameValue) -> syn::Result<LitInt> {
+    match &nv.lit {
+        Lit::Int(li) => Ok(li.clone()),
+        other => Err(syn::Error::new(
+            other.span(),
+            format!(
+                "`{}` must be an integer literal",
+                nv.path.get_ident().map(|i| i.to_string()).unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+/// Parameter types `#[command]` fills from the request context rather than the JSON body.
+const EXTRACTORS: &[&str] = &["PathTail", "State", "CommandContext"];
+
+/// If `ty` names one of the [`EXTRACTORS`], return that name.
+fn extractor_name(ty: &Type) -> Option<&'static str> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let last = type_path.path.segments.last()?;
+    EXTRACTORS.iter().copied().find(|name| last.ident == name)
+}
+
+/// Whether `ty` is `WithWarnings<T>`, possibly as the `Ok` type of a `Result`.
+fn returns_warnings(ty: &Type) -> bool {
+    let Type::Path(type_path) = ty else {
+        return false;
+    };
+    let Some(last) = type_path.path.segments.last() else {
+        return false;
+    };
+    if last.ident == "WithWarnings" {
+        return true;
+    }
+    match &last.arguments {
+        syn::PathArguments::AngleBracketed(args) if last.ident == "Result" => {
+            match args.args.first() {
+                Some(syn::GenericArgument::Type(ok)) => returns_warnings(ok),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Whether `ty` names `CommandResponse`.
+fn is_command_response(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map_or(false, |seg| seg.ident == "CommandResponse"),
+        _ => false,
+    }
+}
+
+/// Whether `ty` is syntactically an `Option` (`Option<T>`, `std::option::Option<T>`).
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map_or(false, |seg| seg.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Whether `ty` is syntactically a `Result` (`Result<T, E>`, `io::Result<T>`, ...).
+fn returns_result(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map_or(false, |seg| seg.ident == "Result"),
+        _ => false,
+    }
+}
+
+/// The type actually sent on success: `T` for `Result<T, ..>`, otherwise `ty` itself.
+fn success_type(ty: &Type) -> Type {
+    if let Type::Path(type_path) = ty {
+        if let Some(last) = type_path.path.segments.last() {
+            if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+                if let (true, Some(syn::GenericArgument::Type(ok))) =
+                    (last.ident == "Result", args.args.first())
+                {
+                    return ok.clone();
                 }
             }
         }
     }
+    ty.clone()
+}
+
+/// `ty` as written, with token-stream spacing tidied (`Vec<u32>`, not `Vec < u32 >`).
+fn type_string(ty: &Type) -> String {
+    tidy_type(&quote!(#ty).to_string())
+}
+
+/// Remove the spaces `to_string` puts between tokens, around `<`, `>` and `::`,
+/// before `,` and `;` and inside `&`, `(` and `)`. Spaces that matter (`dyn Trait`,
+/// `&mut T`) are kept.
+///
+/// The same as `tidy_type` in `wry_cmd_docs`, which can't share it (a proc-macro
+/// crate exports only macros); both pin the same cases in their tests so the type
+/// names in command metadata and in the generated docs stay alike.
+fn tidy_type(s: &str) -> String {
+    s.replace(" < ", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" ,", ",")
+        .replace(" ;", ";")
+        .replace("& ", "&")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
+
+/// The argument type the frontend sends: the single argument's, or a tuple of them.
+fn args_type_string(tys: &[Type]) -> String {
+    match tys {
+        [ty] => type_string(ty),
+        tys => format!(
+            "({})",
+            tys.iter().map(type_string).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Find a reference or lifetime inside `ty`, returning its span.
+fn find_borrow(ty: &Type) -> Option<proc_macro2::Span> {
+    borrow_in(ty, false)
+}
+
+/// Like [`find_borrow`], ignoring `'static` borrows (`&'static str` is fine to return).
+fn find_temporary_borrow(ty: &Type) -> Option<proc_macro2::Span> {
+    borrow_in(ty, true)
+}
+
+fn borrow_in(ty: &Type, allow_static: bool) -> Option<proc_macro2::Span> {
+    let is_static = |lt: &syn::Lifetime| allow_static && lt.ident == "static";
+    match ty {
+        Type::Reference(r) if r.lifetime.as_ref().map_or(false, is_static) => {
+            borrow_in(&r.elem, allow_static)
+        }
+        Type::Reference(r) => Some(r.and_token.span()),
+        Type::Paren(p) => borrow_in(&p.elem, allow_static),
+        Type::Group(g) => borrow_in(&g.elem, allow_static),
+        Type::Slice(s) => borrow_in(&s.elem, allow_static),
+        Type::Array(a) => borrow_in(&a.elem, allow_static),
+        Type::Tuple(t) => t.elems.iter().find_map(|ty| borrow_in(ty, allow_static)),
+        Type::Path(p) => p.path.segments.iter().find_map(|seg| match &seg.arguments {
+            syn::PathArguments::AngleBracketed(args) => {
+                args.args.iter().find_map(|arg| match arg {
+                    syn::GenericArgument::Lifetime(lt) if is_static(lt) => None,
+                    syn::GenericArgument::Lifetime(lt) => Some(lt.span()),
+                    syn::GenericArgument::Type(ty) => borrow_in(ty, allow_static),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }),
+        // `impl Future<Output = T> + '_`
+        Type::ImplTrait(t) => t.bounds.iter().find_map(|bound| match bound {
+            syn::TypeParamBound::Lifetime(lt) if !is_static(lt) => Some(lt.span()),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Marks a function as a Wry IPC command.
+/// The function can take arguments implementing `Deserialize` and return a type
+/// implementing `Serialize`. If omitted, no args or no return are supported.
+/// A single argument is deserialized from the whole JSON body; with several
+/// (`fn add(a: i32, b: i32)`), the body is a positional array (`[1, 2]`).
+/// A `Result<T, E>` return is unwrapped: `Ok` is serialized as the response and
+/// `Err` becomes the command's error (via `E: Display`), sent as `{ "error": ... }`.
+/// Return `wry_cmd::CommandResponse` (directly or as `Ok`) to choose the response
+/// yourself, e.g. raw bytes with their own `Content-Type`.
+/// Use `#[command(name = "...")]` or just `#[command]`. Names (and aliases) may use
+/// ASCII letters, digits, `-` and `_`, with `/` or `.` to separate segments (`files/read`);
+/// anything else couldn't be reached through a URL and fails to compile.
+///
+/// Only free functions can be commands: for methods, put `#[commands]` on the impl.
+///
+/// A sync function gets a plain handler next to the future one. Called through
+/// `handle_command`, it runs on the calling thread; the protocol runs it on a
+/// command thread like async commands, or, with its `inline_sync = true` option,
+/// right on the thread serving the request (wry's event loop), skipping the handoff.
+/// The `sync-only` feature turns `async fn`, `stream` and `max_concurrent` commands into
+/// compile errors, for tools that want every command on this path; handlers are then
+/// plain functions and `futures` drops out of the build.
+///
+/// Add `require_header = "..."` (repeatable) to have the protocol reject requests
+/// missing that header before the command runs.
+///
+/// Add `cache_ttl_ms = ...` to memoize successful results of expensive, pure commands:
+/// identical arguments within the TTL are answered from cache without re-running the handler.
+/// Results are keyed by the arguments (and path tail) only, so it can't be combined with
+/// `State`, `CommandContext` or `context` parameters, nor with `require_header`.
+///
+/// Add `timeout_ms = ...` to fail an async command with `"command timed out"` once it
+/// runs longer than that; its future is dropped. Sync commands can't be interrupted.
+///
+/// Add `max_concurrent = 4` to run at most that many calls at once, e.g. for a command
+/// wrapping a rate-limited API: further calls wait for a slot, or fail right away with
+/// `"too many concurrent requests"` when `reject_when_busy` is also set.
+///
+/// The argument is deserialized as serde would, so a newtype argument
+/// (`struct UserId(u64)`) is sent as its inner value: `42`, not `[42]`.
+///
+/// A single `Option<T>` argument (`fn list(filter: Option<Filter>)`) is `None` when
+/// the request sends no body, `null` or `{}`, and `Some` for anything else.
+///
+/// Add `deserialize_with = "path::to::fn"` to parse the argument with a custom
+/// `fn(serde_json::Value) -> Result<Arg, String>` (`Arg` being a tuple when the command
+/// takes several arguments) instead of `serde_json::from_value`
+/// (strict-mode unknown-field checks are then up to that function).
+///
+/// A `Result` command's error is sent as `e.to_string()`. Add `error = "json"` to send
+/// `serde_json::to_value(&e)` instead (`E: Serialize`), so the frontend gets
+/// `{ "error": { "code": "not_found", ... } }` and can read the error's fields.
+///
+/// Commands returning `()` (or `Result<(), E>`) answer a success with `204 No Content`
+/// and an empty body rather than `null`, so check `res.status` before `res.json()`.
+/// With the `"success-bool"` envelope they keep a `200` body, `{ "success": true, ... }`.
+///
+/// Errors keep the error in the envelope and are sent with a matching status, so
+/// frontends can check `res.ok`: `404` for unknown commands, `400` for arguments that
+/// don't deserialize, and `500` when the command itself fails. Add `error_status = 422`
+/// (any 4xx or 5xx) to send the command's own failures with that status instead.
+/// To pick the status of a success, return a `CommandResponse` built with
+/// `CommandResponse::from(value).with_status(wry_cmd::http::StatusCode::CREATED)`.
+///
+/// Add `detach` for fire-and-forget commands: the protocol responds `202 Accepted`
+/// with `{ "job_id": "..." }` right away and runs the handler in the background,
+/// logging any error since nobody awaits the result.
+///
+/// Add `stream = "text/csv"` (or bare `stream`, for `application/octet-stream`) to a
+/// command returning `impl Stream<Item = Vec<u8>>` (possibly as `Ok`) to send the
+/// streamed bytes as a binary response. wry's responder can't send chunks, so the
+/// stream is collected first, up to `wry_cmd::set_stream_limit` bytes.
+///
+/// Add `method = "GET"` to side-effect-free commands to make them reachable from an
+/// `<img src>` or a plain link: GET requests take their arguments from the query
+/// string (`?name=Alice` → `{ "name": "Alice" }`, values as strings). POST still works;
+/// commands without it answer GET with `405 Method Not Allowed`.
+///
+/// Parameters of extractor types are filled from the request instead of the JSON body
+/// and may appear alongside the argument: a `PathTail` parameter receives the rest of
+/// the request path, so `mado://readfile/path/to/file.txt` reaches `readfile` with
+/// `PathTail("path/to/file.txt")`.
+///
+/// Add `alias = "old_name"` (repeatable) to keep answering to previous names. To plan
+/// their removal, add `alias_deprecated_since = "2.0"` and `alias_removed_in = "3.0"`:
+/// calls through the aliases then log a deprecation warning with that timeline, and
+/// fail with an error pointing at the new name once the app calls
+/// `wry_cmd::gate_removed_aliases` with a version at or past the removal.
+///
+/// Add `deprecated = "use getUser instead"` to phase a command out: every call logs
+/// a warning with the message, and `wry_cmd_docs` marks the command as deprecated.
+///
+/// Add `context` to receive the request's `wry_cmd::CommandContext` (headers, URI, ...)
+/// as the first parameter, before the argument: `fn login(ctx: CommandContext, args: Login)`.
+/// A parameter typed `CommandContext` is recognized without the flag too.
+///
+/// Add `bigint_as_string` to send integers outside JavaScript's safe range (±2^53 - 1),
+/// such as `u64` IDs, as strings instead of numbers the frontend would round.
+///
+/// Add `no_smoke_test` to leave a command with side effects out of the
+/// `wry_cmd::smoke_test!()` test, which otherwise calls every command with `null`.
+///
+/// Add `upgrade` to take over the request entirely: the function must be
+/// `fn(wry_cmd::http::Request<Vec<u8>>, wry_cmd::Responder)` and sends its own response,
+/// now or later from another thread. See `wry_cmd::UpgradeCommand` for what this
+/// can and can't do given wry's custom protocol.
+///
+/// `example = "{\"name\":\"Alice\"}"` is only read by `wry_cmd_docs`, which renders
+/// a `fetch` call sending that JSON body under the command.
+///
+/// `doc_file = "docs/greet.md"` is only read by `wry_cmd_docs`, which uses that file
+/// (relative to the source file) as the command's description instead of its doc comments.
+#[proc_macro_attribute]
+pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
+    // Parse options (`name = "..."`, `require_header = "..."`) from attribute
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let args = match CommandArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     // Parse the function
     let input_fn = parse_macro_input!(item as ItemFn);
     let fn_ident = &input_fn.sig.ident;
 
+    // Registration happens next to the function, which can't be done from inside an
+    // impl block; methods are served through `#[commands]` on the impl instead
+    if let Some(receiver) = input_fn.sig.receiver() {
+        return syn::Error::new(
+            receiver.span(),
+            "`#[command]` can't be used on methods; put `#[commands]` on the impl block \
+             to register its methods, called on the instance from `wry_cmd::set_state`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
     // Determine command name literal
     let default_name = fn_ident.to_string().to_lowercase();
-    let name_lit = override_name.unwrap_or_else(|| LitStr::new(&default_name, fn_ident.span()));
+    let name_lit = args
+        .name
+        .unwrap_or_else(|| LitStr::new(&default_name, fn_ident.span()));
 
-    // Determine if function has a typed argument (excluding receiver)
-    let mut has_arg = false;
-    let mut arg_ty: Type = syn::parse_quote!(serde_json::Value);
-    for input in &input_fn.sig.inputs {
+    // Upgrade commands are registered as-is, bypassing JSON handling entirely
+    if args.upgrade {
+        if let Some(asyncness) = &input_fn.sig.asyncness {
+            return syn::Error::new(
+                asyncness.span(),
+                "upgrade commands must be sync; keep the responder and answer from another thread instead",
+            )
+            .to_compile_error()
+            .into();
+        }
+        let expanded = quote! {
+            #input_fn
+
+            ::wry_cmd::inventory::submit! {
+                ::wry_cmd::UpgradeCommand {
+                    name: #name_lit,
+                    handler: #fn_ident,
+                }
+            }
+        };
+        return expanded.into();
+    }
+
+    let required_headers = &args.required_headers;
+    let detach = args.detach;
+    let method = args
+        .method
+        .clone()
+        .unwrap_or_else(|| LitStr::new("POST", proc_macro2::Span::call_site()));
+    let smoke_test = !args.no_smoke_test;
+    let cache_ttl_ms = match &args.cache_ttl_ms {
+        Some(ttl) => quote! { ::core::option::Option::Some(#ttl) },
+        None => quote! { ::core::option::Option::None },
+    };
+    let timeout = match &args.timeout_ms {
+        Some(ms) => {
+            quote! { ::core::option::Option::Some(::core::time::Duration::from_millis(#ms)) }
+        }
+        None => quote! { ::core::option::Option::None },
+    };
+    let max_concurrent = match &args.max_concurrent {
+        Some(max) => quote! { ::core::option::Option::Some(#max) },
+        None => quote! { ::core::option::Option::None },
+    };
+    if let (Some(flag), None) = (&args.reject_when_busy, &args.max_concurrent) {
+        return syn::Error::new(flag.span(), "`reject_when_busy` requires `max_concurrent`")
+            .to_compile_error()
+            .into();
+    }
+    let reject_when_busy = args.reject_when_busy.is_some();
+    let error_status = match &args.error_status {
+        Some(status) => quote! { ::core::option::Option::Some(#status) },
+        None => quote! { ::core::option::Option::None },
+    };
+    let optional_str = |lit: &Option<LitStr>| match lit {
+        Some(lit) => quote! { ::core::option::Option::Some(#lit) },
+        None => quote! { ::core::option::Option::None },
+    };
+    let deprecated_since = optional_str(&args.alias_deprecated_since);
+    let removed_in = optional_str(&args.alias_removed_in);
+    let deprecated = optional_str(&args.deprecated);
+    let aliases = args.aliases.iter().map(|alias| {
+        quote! {
+            ::wry_cmd::Alias {
+                name: #alias,
+                deprecated_since: #deprecated_since,
+                removed_in: #removed_in,
+            }
+        }
+    });
+
+    // Sort typed parameters (excluding receiver) into extractors, filled from the
+    // request context, and arguments deserialized from the JSON body
+    let mut accepts_path_tail = false;
+    // The first parameter filled from state or the request, other than the path tail
+    let mut context_param = None;
+    let mut extract = Vec::new();
+    let mut call_args = Vec::new();
+    let mut arg_idents = Vec::new();
+    let mut arg_tys = Vec::new();
+    let mut first = true;
+    for (i, input) in input_fn.sig.inputs.iter().enumerate() {
         if let FnArg::Typed(PatType { ty, .. }) = input {
-            has_arg = true;
-            arg_ty = (*ty.clone());
-            break;
+            // with `context`, the first parameter is the context whatever its type is called
+            let extractor = match extractor_name(ty) {
+                None if args.context && first => Some("CommandContext"),
+                other => other,
+            };
+            first = false;
+            if let Some(extractor) = extractor {
+                accepts_path_tail |= extractor == "PathTail";
+                if extractor != "PathTail" && context_param.is_none() {
+                    context_param = Some(ty.span());
+                }
+                let ident = format_ident!("__extract_{}", i);
+                extract.push(quote! {
+                    let #ident = match <#ty as ::wry_cmd::FromContext>::from_context(&ctx) {
+                        Ok(v) => v,
+                        Err(e) => return Err(::wry_cmd::DispatchError::Handler(e)),
+                    };
+                });
+                call_args.push(quote! { #ident });
+            } else {
+                // Handler futures are boxed as `'static`, so arguments must be owned
+                if let Some(borrow) = find_borrow(ty) {
+                    return syn::Error::new(
+                        borrow,
+                        "command arguments must be owned: the handler future is boxed as \
+                         `'static` and the argument is deserialized into it \
+                         (e.g. take `String` instead of `&str`)",
+                    )
+                    .to_compile_error()
+                    .into();
+                }
+                let ident = format_ident!("__arg_{}", i);
+                call_args.push(quote! { #ident });
+                arg_idents.push(ident);
+                arg_tys.push((**ty).clone());
+            }
+        }
+    }
+
+    // Cached results are keyed by the arguments and path tail only, so a command
+    // depending on anything else could be answered with another caller's result
+    if args.cache_ttl_ms.is_some() {
+        let reason = match (context_param, args.required_headers.first()) {
+            (Some(span), _) => Some((
+                span,
+                "`cache_ttl_ms` can't be used on commands reading state or the request \
+                 context: cached results are keyed by the arguments only",
+            )),
+            (None, Some(header)) => Some((
+                header.span(),
+                "`cache_ttl_ms` can't be used with `require_header`: cached results are \
+                 keyed by the arguments only, so they'd be shared across callers",
+            )),
+            (None, None) => None,
+        };
+        if let Some((span, message)) = reason {
+            return syn::Error::new(span, message).to_compile_error().into();
         }
     }
 
+    // A single argument is the whole JSON body, as before; several are
+    // deserialized as a tuple from a positional array (`[1, 2]`)
+    let has_arg = !arg_tys.is_empty();
+    let (arg_ty, arg_pat): (Type, proc_macro2::TokenStream) = match arg_tys.as_slice() {
+        [ty] => {
+            let ident = &arg_idents[0];
+            (ty.clone(), quote! { #ident })
+        }
+        tys => (
+            parse_quote!((#(#tys,)*)),
+            quote! { (#(#arg_idents,)*) },
+        ),
+    };
+
     // Determine return type or default to `()`
     let mut has_return = true;
     let ret_ty: Type = match &input_fn.sig.output {
@@ -62,65 +689,228 @@ pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
     // Detect async vs sync
     let is_async = input_fn.sig.asyncness.is_some();
 
-    // Build the handler closure
-    let handler = if is_async {
-        if has_arg {
-            quote! {{
-                use ::wry_cmd::futures::future::FutureExt;
-                |args: ::serde_json::Value| {
-                    async move {
-                        let args: #arg_ty = match ::serde_json::from_value(args) {
-                            Ok(v) => v,
-                            Err(e) => return Err(e.to_string()),
-                        };
-                        let ret = #fn_ident(args).await;
-                        ::serde_json::to_value(&ret).map_err(|e| e.to_string())
+    // With `sync-only`, every command must run straight from the protocol handler
+    if cfg!(feature = "sync-only") {
+        let offending = match (&input_fn.sig.asyncness, &args.stream) {
+            (Some(asyncness), _) => Some(asyncness.span()),
+            (None, Some(stream)) => Some(stream.span()),
+            (None, None) => None,
+        };
+        if let Some(span) = offending {
+            return syn::Error::new(
+                span,
+                "async and stream commands aren't available with the `sync-only` feature",
+            )
+            .to_compile_error()
+            .into();
+        }
+        // Concurrency limits wait for a slot asynchronously
+        if let Some(max) = &args.max_concurrent {
+            return syn::Error::new(
+                max.span(),
+                "`max_concurrent` isn't available with the `sync-only` feature",
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+
+    if let (Some(path), false) = (&args.deserialize_with, has_arg) {
+        return syn::Error::new(
+            path.span(),
+            "`deserialize_with` requires the command to take an argument",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    // Deserialize the argument (if any) and build the call
+    let deserialize = match &args.deserialize_with {
+        Some(path) => quote! {{
+            let _ = &ctx;
+            #path(args)
+        }},
+        // A lone `Option` argument is `None` when nothing is sent: no body, `null` or `{}`
+        None if arg_tys.len() == 1 && is_option(&arg_tys[0]) => {
+            quote! { ::wry_cmd::from_args(::wry_cmd::empty_as_null(args), &ctx) }
+        }
+        None => quote! { ::wry_cmd::from_args(args, &ctx) },
+    };
+    // Argument errors name the command and argument type, e.g.
+    // "greet: invalid args for GreetArgs: missing field `name`"
+    let (parse_args, call) = if has_arg {
+        let prefix = format!(
+            "{{}}: invalid args for {}: {{}}",
+            args_type_string(&arg_tys).replace('{', "{{").replace('}', "}}")
+        );
+        (
+            quote! {
+                let #arg_pat: #arg_ty = match #deserialize {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(::wry_cmd::DispatchError::BadArgs(::std::format!(
+                            #prefix, #name_lit, e
+                        )))
                     }
-                    .boxed()
+                };
+            },
+            quote! { #fn_ident(#(#call_args),*) },
+        )
+    } else {
+        // no arguments
+        (
+            quote! {
+                if let Err(e) = ::wry_cmd::check_no_args(&args, &ctx) {
+                    return Err(::wry_cmd::DispatchError::BadArgs(::std::format!(
+                        "{}: {}", #name_lit, e
+                    )));
                 }
-            }}
+            },
+            quote! { #fn_ident(#(#call_args),*) },
+        )
+    };
+    let call = if is_async {
+        // Spanned at the function so a future that isn't `Send + 'static`
+        // is reported on the command instead of deep inside `.boxed()`
+        let sig_span = input_fn.sig.span();
+        quote_spanned! {sig_span=> ::wry_cmd::assert_command_future(#call).await }
+    } else {
+        call
+    };
+
+    let returns_warnings = returns_warnings(&ret_ty);
+    let returns_unit = matches!(success_type(&ret_ty), Type::Tuple(t) if t.elems.is_empty());
+
+    // `Result` returns are unwrapped: `Ok` is the response, `Err` the command's error.
+    // Streams are collected into bytes, a `CommandResponse` is passed through and
+    // anything else is serialized straight to JSON bytes, skipping `Value`.
+    let bigint_target = args.stream.is_some() || is_command_response(&success_type(&ret_ty));
+    if let (Some(flag), true) = (&args.bigint_as_string, bigint_target) {
+        return syn::Error::new(
+            flag.span(),
+            "`bigint_as_string` only applies to commands returning serializable values",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let to_value = if let Some(content_type) = &args.stream {
+        quote! { ::wry_cmd::collect_stream(#content_type, ret).await }
+    } else if is_command_response(&success_type(&ret_ty)) {
+        quote! { Ok(ret) }
+    } else if args.bigint_as_string.is_some() {
+        // Large integers are only visible in a `Value`, so this skips the bytes path
+        quote! {
+            ::serde_json::to_value(&ret)
+                .map(|value| ::wry_cmd::CommandResponse::Json(::wry_cmd::bigints_as_strings(value)))
+                .map_err(|e| ::wry_cmd::DispatchError::Handler(e.to_string()))
+        }
+    } else {
+        quote! {
+            ::serde_json::to_vec(&ret)
+                .map(::wry_cmd::CommandResponse::RawJson)
+                .map_err(|e| ::wry_cmd::DispatchError::Handler(e.to_string()))
+        }
+    };
+    if let (Some(error), false) = (&args.json_error, returns_result(&ret_ty)) {
+        return syn::Error::new(error.span(), "`error = \"json\"` requires a `Result` return")
+            .to_compile_error()
+            .into();
+    }
+    let to_error = if args.json_error.is_some() {
+        quote! {
+            ::wry_cmd::DispatchError::Structured(
+                ::serde_json::to_value(&e)
+                    .unwrap_or_else(|e| ::serde_json::Value::String(e.to_string())),
+            )
+        }
+    } else {
+        quote! { ::wry_cmd::DispatchError::Handler(::std::string::ToString::to_string(&e)) }
+    };
+    let serialize = if returns_result(&ret_ty) {
+        quote! {
+            match ret {
+                Ok(ret) => #to_value,
+                Err(e) => Err(#to_error),
+            }
+        }
+    } else {
+        to_value
+    };
+
+    // Per-command schemas, only with the `introspection` feature
+    let schema = if cfg!(feature = "introspection") {
+        let args_schema = if has_arg {
+            quote! { ::wry_cmd::schemars::schema_for!(#arg_ty) }
         } else {
-            // no arguments
-            quote! {{
-                use ::wry_cmd::futures::future::FutureExt;
-                |_: ::serde_json::Value| {
-                    async move {
-                        let ret = #fn_ident().await;
-                        ::serde_json::to_value(&ret).map_err(|e| e.to_string())
-                    }
-                    .boxed()
-                }
-            }}
+            quote! { ::serde_json::Value::Null }
+        };
+        let returns_ty = success_type(&ret_ty);
+        // binary or hand-built responses have no fixed schema
+        let returns_schema = if args.stream.is_some() || is_command_response(&returns_ty) {
+            quote! { ::serde_json::Value::Null }
+        } else {
+            quote! { ::wry_cmd::schemars::schema_for!(#returns_ty) }
+        };
+        quote! {
+            ::core::option::Option::Some(|| {
+                ::serde_json::json!({
+                    "args": #args_schema,
+                    "returns": #returns_schema,
+                })
+            })
         }
     } else {
-        if has_arg {
-            quote! {{
-                use ::wry_cmd::futures::future::FutureExt;
-                |args: ::serde_json::Value| {
-                    async move {
-                        let args: #arg_ty = match ::serde_json::from_value(args) {
-                            Ok(v) => v,
-                            Err(e) => return Err(e.to_string()),
-                        };
-                        let ret = #fn_ident(args);
-                        ::serde_json::to_value(&ret).map_err(|e| e.to_string())
-                    }
-                    .boxed()
+        quote! { ::core::option::Option::None }
+    };
+
+    // Type names for runtime introspection
+    let args_type = if has_arg {
+        let args_type = args_type_string(&arg_tys);
+        quote! { ::core::option::Option::Some(#args_type) }
+    } else {
+        quote! { ::core::option::Option::None }
+    };
+    let returns_type = type_string(&success_type(&ret_ty));
+
+    // Build the handler closure. Sync commands also get a future-free handler
+    // the runtime calls directly; the boxed one just wraps it.
+    let (handler, sync_handler) = if is_async || args.stream.is_some() {
+        let handler = quote! {{
+            use ::wry_cmd::futures::future::FutureExt;
+            |ctx: ::wry_cmd::CommandContext, args: ::serde_json::Value| {
+                async move {
+                    #(#extract)*
+                    #parse_args
+                    let ret = #call;
+                    #serialize
                 }
-            }}
+                .boxed()
+            }
+        }};
+        (handler, quote! { ::core::option::Option::None })
+    } else {
+        let sync = quote! {
+            |ctx: ::wry_cmd::CommandContext,
+             args: ::serde_json::Value|
+             -> ::core::result::Result<::wry_cmd::CommandResponse, ::wry_cmd::DispatchError> {
+                #(#extract)*
+                #parse_args
+                let ret = #call;
+                #serialize
+            }
+        };
+        // With `sync-only`, handlers are plain functions and there's nothing to wrap
+        let handler = if cfg!(feature = "sync-only") {
+            sync.clone()
         } else {
-            // no arguments
             quote! {{
                 use ::wry_cmd::futures::future::FutureExt;
-                |_: ::serde_json::Value| {
-                    async move {
-                        let ret = #fn_ident();
-                        ::serde_json::to_value(&ret).map_err(|e| e.to_string())
-                    }
-                    .boxed()
+                |ctx: ::wry_cmd::CommandContext, args: ::serde_json::Value| {
+                    ::wry_cmd::futures::future::ready((#sync)(ctx, args)).boxed()
                 }
             }}
-        }
+        };
+        (handler, quote! { ::core::option::Option::Some(#sync) })
     };
 
     // Emit the original function and inventory registration
@@ -130,13 +920,139 @@ pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
         ::wry_cmd::inventory::submit! {
             ::wry_cmd::Command {
                 name: #name_lit,
-                handler: #handler
+                handler: #handler,
+                sync_handler: #sync_handler,
+                required_headers: &[#(#required_headers),*],
+                cache_ttl_ms: #cache_ttl_ms,
+                timeout: #timeout,
+                max_concurrent: #max_concurrent,
+                reject_when_busy: #reject_when_busy,
+                detach: #detach,
+                method: #method,
+                accepts_path_tail: #accepts_path_tail,
+                aliases: &[#(#aliases),*],
+                deprecated: #deprecated,
+                smoke_test: #smoke_test,
+                returns_warnings: #returns_warnings,
+                returns_unit: #returns_unit,
+                error_status: #error_status,
+                schema: #schema,
+                args_type: #args_type,
+                returns_type: #returns_type,
             }
         }
     };
     expanded.into()
 }
 
+/// Whether `attr` is `#[command(skip)]`, marking a `#[commands]` method as a helper.
+fn is_skip_attr(attr: &syn::Attribute) -> bool {
+    if !attr.path.is_ident("command") {
+        return false;
+    }
+    match attr.parse_meta() {
+        Ok(Meta::List(list)) => list
+            .nested
+            .iter()
+            .any(|nested| matches!(nested, NestedMeta::Meta(Meta::Path(p)) if p.is_ident("skip"))),
+        _ => false,
+    }
+}
+
+/// Map each generic parameter of a `#[commands]` impl to the matching argument of
+/// the concrete `instance` type, by its position in the impl's self type:
+/// `impl<T> Svc<T>` with `instance = "Svc<Config>"` maps `T` to `Config`.
+fn generic_substitutions(
+    input_impl: &ItemImpl,
+    instance: Option<&Type>,
+) -> syn::Result<Vec<(proc_macro2::Ident, proc_macro2::TokenStream)>> {
+    let generics = &input_impl.generics;
+    if generics.params.is_empty() {
+        return Ok(Vec::new());
+    }
+    let instance = instance.ok_or_else(|| {
+        syn::Error::new(
+            generics.span(),
+            "generic `#[commands]` impls need the concrete type the instance is \
+             registered as, e.g. #[commands(instance = \"MyService<Config>\")]",
+        )
+    })?;
+    let type_args = |ty: &Type| -> Vec<proc_macro2::TokenStream> {
+        match ty {
+            Type::Path(type_path) => match &type_path.path.segments.last().unwrap().arguments {
+                syn::PathArguments::AngleBracketed(args) => {
+                    args.args.iter().map(|arg| quote! { #arg }).collect()
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    };
+    let generic_args = type_args(&input_impl.self_ty);
+    let concrete_args = type_args(instance);
+    if generic_args.len() != concrete_args.len() {
+        return Err(syn::Error::new(
+            instance.span(),
+            format!(
+                "`instance` must have the same {} generic argument(s) as the impl's type",
+                generic_args.len()
+            ),
+        ));
+    }
+    let mut substitutions = Vec::new();
+    for param in &generics.params {
+        let ident = match param {
+            syn::GenericParam::Type(param) => &param.ident,
+            syn::GenericParam::Const(param) => &param.ident,
+            syn::GenericParam::Lifetime(param) => {
+                return Err(syn::Error::new(
+                    param.span(),
+                    "`#[commands]` impls can't have lifetime parameters: \
+                     the instance must be `'static`",
+                ))
+            }
+        };
+        let position = generic_args.iter().position(|arg| arg.to_string() == ident.to_string());
+        match position {
+            Some(i) => substitutions.push((ident.clone(), concrete_args[i].clone())),
+            None => {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "generic parameters of `#[commands]` impls must appear directly as \
+                     arguments of the impl's type",
+                ))
+            }
+        }
+    }
+    Ok(substitutions)
+}
+
+/// Replace every identifier in `substitutions` within `tokens`.
+fn substitute(
+    tokens: proc_macro2::TokenStream,
+    substitutions: &[(proc_macro2::Ident, proc_macro2::TokenStream)],
+) -> proc_macro2::TokenStream {
+    use proc_macro2::{Group, TokenTree};
+    tokens
+        .into_iter()
+        .flat_map(|tree| -> proc_macro2::TokenStream {
+            match tree {
+                TokenTree::Ident(ident) => match substitutions.iter().find(|(p, _)| *p == ident) {
+                    Some((_, replacement)) => replacement.clone(),
+                    None => TokenTree::Ident(ident).into(),
+                },
+                TokenTree::Group(group) => {
+                    let stream = substitute(group.stream(), substitutions);
+                    let mut replaced = Group::new(group.delimiter(), stream);
+                    replaced.set_span(group.span());
+                    TokenTree::Group(replaced).into()
+                }
+                other => other.into(),
+            }
+        })
+        .collect()
+}
+
 /// Attribute macro to auto-generate and register IPC commands from an impl block.
 ///
 /// Usage:
@@ -153,24 +1069,135 @@ pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// #[commands(service = "foo")]
 /// impl MyTrait for MyStruct { … }
 /// ```
+///
+/// Commands are named `<service>/<method>`. Use `#[commands(separator = ".")]` for
+/// `<service>.<method>`, or set the default for the whole crate by building with
+/// `WRY_CMD_SEPARATOR=.` (e.g. in `.cargo/config.toml`'s `[env]`; Cargo doesn't
+/// rebuild when only that variable changes). Calls resolve with either separator.
+///
+/// `#[commands(error = "json")]` sends the errors of every `Result`-returning method
+/// as JSON, like `#[command(error = "json")]`.
+///
+/// Every method becomes a command; mark helpers with `#[command(skip)]` to leave
+/// them out while keeping them in the impl. Other `#[command(...)]` options on a
+/// method apply to its command as they would on a function (`name = "x"` renames
+/// it to `<service>/x`), except `context` and `upgrade`.
+///
+/// Methods are called on the instance registered with `wry_cmd::set_state`
+/// (`set_state(MyStruct::new())`); until then, these commands fail with an error.
+/// If any method takes `&mut self`, register it with `wry_cmd::set_state_mutex`
+/// instead, which keeps it behind a `std::sync::RwLock`: `&self` methods then run
+/// under a read lock, concurrently, and `&mut self` ones under the write lock, one at
+/// a time. Such impls can't have `async fn`s, since the lock can't be held across an
+/// `.await`.
+///
+/// Generic impls (`impl<T: Backend> MyService<T>`) need the concrete type the
+/// instance is registered as: `#[commands(instance = "MyService<SqliteBackend>")]`.
+/// The wrappers are generated for that type only, with the impl's parameters replaced
+/// in argument and return types, and `set_state(MyService::new(SqliteBackend))` provides
+/// the instance. To serve another instantiation, add a second `#[commands]` impl block
+/// with its own `service` name.
+///
+/// `async fn`s may borrow `self` across `.await`s: the wrapper holds the instance
+/// until the call completes. What goes in and out must be owned, since the command
+/// future is `'static`: take `String` rather than `&str`, and return owned data
+/// rather than references into `self`.
 #[proc_macro_attribute]
 pub fn commands(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // 1. Parse optional `service = "..."` from attribute
+    // 1. Parse optional `service = "..."` and `separator = "..."` from attribute
     let args = parse_macro_input!(attr as AttributeArgs);
     let mut override_service: Option<LitStr> = None;
+    let mut separator: Option<LitStr> = None;
+    let mut instance: Option<Type> = None;
+    let mut json_error = false;
     for nested in args {
         if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
             if nv.path.is_ident("service") {
-                match nv.lit {
-                    Lit::Str(ls) => override_service = Some(ls),
-                    _ => panic!("`service` attribute must be a string, e.g. service = \"foo\""),
+                override_service = match &nv.lit {
+                    Lit::Str(ls) => Some(ls.clone()),
+                    other => {
+                        return syn::Error::new(
+                            other.span(),
+                            "`service` must be a string, e.g. service = \"foo\"",
+                        )
+                        .to_compile_error()
+                        .into()
+                    }
+                };
+            } else if nv.path.is_ident("separator") {
+                separator = match lit_str(&nv) {
+                    Ok(lit) => Some(lit),
+                    Err(e) => return e.to_compile_error().into(),
+                };
+            } else if nv.path.is_ident("error") {
+                match lit_str(&nv) {
+                    Ok(lit) if lit.value() == "json" => json_error = true,
+                    Ok(lit) if lit.value() == "string" => json_error = false,
+                    Ok(lit) => {
+                        return syn::Error::new(lit.span(), "`error` must be \"json\" or \"string\"")
+                            .to_compile_error()
+                            .into()
+                    }
+                    Err(e) => return e.to_compile_error().into(),
                 }
+            } else if nv.path.is_ident("instance") {
+                instance = match lit_str(&nv).and_then(|lit| lit.parse::<Type>()) {
+                    Ok(ty) => Some(ty),
+                    Err(e) => return e.to_compile_error().into(),
+                };
             }
         }
     }
+    // The crate-wide default comes from `WRY_CMD_SEPARATOR` at build time
+    let separator = match separator {
+        Some(lit) => lit,
+        None => {
+            let value = std::env::var("WRY_CMD_SEPARATOR").unwrap_or_else(|_| "/".to_string());
+            LitStr::new(&value, proc_macro2::Span::call_site())
+        }
+    };
+    if separator.value() != "/" && separator.value() != "." {
+        return syn::Error::new(separator.span(), "`separator` must be \"/\" or \".\"")
+            .to_compile_error()
+            .into();
+    }
 
-    // 2. Parse the impl block
-    let input_impl = parse_macro_input!(item as ItemImpl);
+    // 2. Parse the impl block. `#[command(skip)]` keeps a helper method out of the
+    //    commands; any other `#[command(...)]` holds options for the method's command.
+    //    Both are removed so the impl compiles as written.
+    let mut input_impl = parse_macro_input!(item as ItemImpl);
+    let mut skipped = Vec::new();
+    let mut method_options: Vec<(syn::Ident, Vec<NestedMeta>)> = Vec::new();
+    for item in &mut input_impl.items {
+        if let ImplItem::Method(m) = item {
+            let attr_count = m.attrs.len();
+            m.attrs.retain(|attr| !is_skip_attr(attr));
+            if m.attrs.len() != attr_count {
+                skipped.push(m.sig.ident.clone());
+                continue;
+            }
+            let mut options = Vec::new();
+            let mut error = None;
+            m.attrs.retain(|attr| {
+                if !attr.path.is_ident("command") {
+                    return true;
+                }
+                match attr.parse_meta() {
+                    Ok(Meta::List(list)) => options.extend(list.nested),
+                    Ok(Meta::Path(_)) => {}
+                    Ok(other) => {
+                        error = Some(syn::Error::new(other.span(), "expected `#[command(...)]`"))
+                    }
+                    Err(e) => error = Some(e),
+                }
+                false
+            });
+            if let Some(e) = error {
+                return e.to_compile_error().into();
+            }
+            method_options.push((m.sig.ident.clone(), options));
+        }
+    }
 
     // 3. Determine the service name literal
     let service_lit = if let Some(s) = override_service {
@@ -184,31 +1211,118 @@ pub fn commands(attr: TokenStream, item: TokenStream) -> TokenStream {
         let ty_name = if let Type::Path(type_path) = &*input_impl.self_ty {
             type_path.path.segments.last().unwrap().ident.to_string()
         } else {
-            panic!("`#[commands]` only supports impls on simple path types");
+            return syn::Error::new(
+                input_impl.self_ty.span(),
+                "`#[commands]` needs a named type (`impl MyService`) to name the service; \
+                 for other types, set one with #[commands(service = \"...\")]",
+            )
+            .to_compile_error()
+            .into();
         };
         LitStr::new(&ty_name.to_lowercase(), input_impl.self_ty.span())
     };
 
-    // 4. Build one wrapper per method
+    // 4. Generic impls are bound to the concrete `instance` type: its arguments
+    //    stand in for the impl's parameters wherever a wrapper names them
+    let substitutions = match generic_substitutions(&input_impl, instance.as_ref()) {
+        Ok(substitutions) => substitutions,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let concrete = |tokens: proc_macro2::TokenStream| substitute(tokens, &substitutions);
+    let self_ty = match &instance {
+        Some(ty) => quote! { #ty },
+        None => {
+            let self_ty = &input_impl.self_ty;
+            quote! { #self_ty }
+        }
+    };
+
+    // 5. Build one wrapper per method. With any `&mut self` method, the instance
+    //    lives in an `RwLock`: `&self` wrappers read-lock it, `&mut self` ones write-lock it.
+    let takes_mut_self = |m: &syn::ImplItemMethod| {
+        matches!(
+            m.sig.receiver(),
+            Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_some()
+        )
+    };
+    let locked = input_impl.items.iter().any(|item| match item {
+        ImplItem::Method(m) => takes_mut_self(m) && !skipped.contains(&m.sig.ident),
+        _ => false,
+    });
     let mut wrappers = Vec::new();
     for item in &input_impl.items {
         if let ImplItem::Method(m) = item {
             let method_ident = &m.sig.ident;
-            let wrapper_ident = format_ident!("__cmd_{}_{}", service_lit.value(), method_ident);
-            // final command name: "<service>/<method>"
+            if skipped.contains(method_ident) {
+                continue;
+            }
+            // Service names may hold `-`, `.` or `/`, which can't go in an identifier
+            let service_ident: String = service_lit
+                .value()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            let wrapper_ident = format_ident!("__cmd_{}_{}", service_ident, method_ident);
+
+            // The method's own `#[command(...)]` options go on its wrapper; `name`
+            // replaces the method part of the command name
+            let options = method_options
+                .iter()
+                .find(|(ident, _)| ident == method_ident)
+                .map_or(&[][..], |(_, options)| options.as_slice());
+            let mut method_name = method_ident.to_string();
+            let mut forwarded = Vec::new();
+            let mut own_error = false;
+            for option in options {
+                match option {
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                        match lit_str(nv) {
+                            Ok(lit) => method_name = lit.value(),
+                            Err(e) => return e.to_compile_error().into(),
+                        }
+                    }
+                    NestedMeta::Meta(meta)
+                        if meta.path().is_ident("context") || meta.path().is_ident("upgrade") =>
+                    {
+                        return syn::Error::new(
+                            meta.span(),
+                            "`context` and `upgrade` aren't available on `#[commands]` methods: \
+                             the instance is the wrapper's first parameter",
+                        )
+                        .to_compile_error()
+                        .into();
+                    }
+                    other => {
+                        own_error |= matches!(
+                            other,
+                            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("error")
+                        );
+                        forwarded.push(other.clone());
+                    }
+                }
+            }
+
+            // final command name: "<service>/<method>" (or "<service>.<method>")
             let cmd_name = LitStr::new(
-                &format!("{}/{}", service_lit.value(), method_ident),
+                &format!("{}{}{}", service_lit.value(), separator.value(), method_name),
                 method_ident.span(),
             );
 
-            // detect if there’s a single typed argument
-            let mut has_arg = false;
-            let mut arg_ty: Type = parse_quote!(serde_json::Value);
-            for input in &m.sig.inputs {
+            // forward every typed argument; the instance comes from `set_state`
+            // (or `set_state_mutex`)
+            let state_ty = if locked {
+                quote! { ::wry_cmd::State<::std::sync::RwLock<#self_ty>> }
+            } else {
+                quote! { ::wry_cmd::State<#self_ty> }
+            };
+            let mut params = vec![quote! { __state: #state_ty }];
+            let mut forward = Vec::new();
+            for (i, input) in m.sig.inputs.iter().enumerate() {
                 if let FnArg::Typed(PatType { ty, .. }) = input {
-                    has_arg = true;
-                    arg_ty = (*ty.clone());
-                    break;
+                    let ident = format_ident!("__arg_{}", i);
+                    let ty = concrete(quote! { #ty });
+                    params.push(quote! { #ident: #ty });
+                    forward.push(ident);
                 }
             }
 
@@ -217,38 +1331,76 @@ pub fn commands(attr: TokenStream, item: TokenStream) -> TokenStream {
                 ReturnType::Default => parse_quote!(()),
                 ReturnType::Type(_, ty) => (*ty.clone()),
             };
+            // The wrapper owns the instance handle only while the call runs, so
+            // nothing borrowed from `self` (or the arguments) can be returned
+            if let Some(borrow) = find_temporary_borrow(&ret_ty) {
+                return syn::Error::new(
+                    borrow,
+                    "command methods must return owned data: the result is sent after \
+                     the call, when `self` is no longer borrowed \
+                     (e.g. return `String` instead of `&str`)",
+                )
+                .to_compile_error()
+                .into();
+            }
+            // A service-wide `error = "json"` applies to the methods that can fail,
+            // unless the method sets its own
+            let error_option = if json_error && !own_error && returns_result(&ret_ty) {
+                quote! { , error = "json" }
+            } else {
+                quote! {}
+            };
+            let ret_ty = concrete(quote! { #ret_ty });
+
+            if let (true, Some(asyncness)) = (cfg!(feature = "sync-only"), &m.sig.asyncness) {
+                return syn::Error::new(
+                    asyncness.span(),
+                    "async command methods aren't available with the `sync-only` feature",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            // A std lock guard can't be held across an `.await`
+            if let (true, Some(asyncness)) = (locked, &m.sig.asyncness) {
+                return syn::Error::new(
+                    asyncness.span(),
+                    "async methods can't be mixed with `&mut self` methods in `#[commands]`: \
+                     the instance is behind a `std::sync::RwLock`, which can't be held \
+                     across an `.await`",
+                )
+                .to_compile_error()
+                .into();
+            }
 
             // generate wrapper
-            let wrapper = if m.sig.asyncness.is_some() {
-                if has_arg {
-                    quote! {
-                        #[wry_cmd::command(name = #cmd_name)]
-                        async fn #wrapper_ident(args: #arg_ty) -> #ret_ty {
-                            INSTANCE.#method_ident(args).await
-                        }
-                    }
+            let wrapper = if locked {
+                // A poisoned lock only means another call panicked; the instance is
+                // still there
+                let guard = if takes_mut_self(m) {
+                    quote! { let mut __guard = __state.write().unwrap_or_else(|e| e.into_inner()); }
                 } else {
-                    quote! {
-                        #[wry_cmd::command(name = #cmd_name)]
-                        async fn #wrapper_ident() -> #ret_ty {
-                            INSTANCE.#method_ident().await
-                        }
+                    quote! { let __guard = __state.read().unwrap_or_else(|e| e.into_inner()); }
+                };
+                quote! {
+                    #[wry_cmd::command(name = #cmd_name #error_option #(, #forwarded)*)]
+                    fn #wrapper_ident(#(#params),*) -> #ret_ty {
+                        #guard
+                        __guard.#method_ident(#(#forward),*)
                     }
                 }
-            } else {
-                if has_arg {
-                    quote! {
-                        #[wry_cmd::command(name = #cmd_name)]
-                        fn #wrapper_ident(args: #arg_ty) -> #ret_ty {
-                            INSTANCE.#method_ident(args)
-                        }
+            } else if m.sig.asyncness.is_some() {
+                quote! {
+                    #[wry_cmd::command(name = #cmd_name #error_option #(, #forwarded)*)]
+                    async fn #wrapper_ident(#(#params),*) -> #ret_ty {
+                        __state.#method_ident(#(#forward),*).await
                     }
-                } else {
-                    quote! {
-                        #[wry_cmd::command(name = #cmd_name)]
-                        fn #wrapper_ident() -> #ret_ty {
-                            INSTANCE.#method_ident()
-                        }
+                }
+            } else {
+                quote! {
+                    #[wry_cmd::command(name = #cmd_name #error_option #(, #forwarded)*)]
+                    fn #wrapper_ident(#(#params),*) -> #ret_ty {
+                        __state.#method_ident(#(#forward),*)
                     }
                 }
             };
@@ -257,10 +1409,32 @@ pub fn commands(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
-    // 5. Re-emit the original impl plus all wrappers
+    // 6. Re-emit the original impl plus all wrappers
     let expanded = quote! {
         #input_impl
         #(#wrappers)*
     };
     expanded.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_strings_are_tidy() {
+        for (ty, expected) in [
+            ("Result<Vec<Option<T>>, String>", "Result<Vec<Option<T>>, String>"),
+            ("&'a mut T", "&'a mut T"),
+            ("&[u8]", "&[u8]"),
+            ("[u8; 4]", "[u8; 4]"),
+            ("dyn Trait", "dyn Trait"),
+            ("Box<dyn Trait>", "Box<dyn Trait>"),
+            ("(u32, String)", "(u32, String)"),
+            ("std::collections::HashMap<String, u32>", "std::collections::HashMap<String, u32>"),
+        ] {
+            let parsed: Type = syn::parse_str(ty).unwrap();
+            assert_eq!(type_string(&parsed), expected);
+        }
+    }
+}